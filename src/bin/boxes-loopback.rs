@@ -0,0 +1,105 @@
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use boxes::{
+    Boxes,
+    Conditions,
+    GameMode
+};
+
+// Plays two rollback peers against each other on localhost, each in its own process with
+// random input and simulated network conditions, and checks they end up in the same state.
+fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    let arg = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1));
+    let frames = arg("--frames").and_then(|t| t.parse::<u64>().ok()).unwrap_or(1200);
+    let latency = arg("--latency").and_then(|t| t.parse::<u64>().ok()).unwrap_or(60);
+    let loss = arg("--loss").and_then(|t| t.parse::<f64>().ok()).unwrap_or(5.0);
+    let seed = arg("--seed").and_then(|t| t.parse::<u64>().ok()).unwrap_or(1);
+    let versus = args.iter().any(|a| a == "--versus");
+
+    if let (Some(listen), Some(peer)) = (arg("--listen"), arg("--peer")) {
+        boxes::set_debug(false);
+        let mut boxes = Boxes::new(800, 800, 1, 10);
+        boxes.set_seed(seed);
+        if versus {
+            boxes.set_mode(GameMode::Versus);
+        }
+        let conditions = Conditions {
+            latency: Duration::from_millis(latency),
+            loss: loss / 100.0,
+        };
+        if let Err(e) = boxes.connect_peer(listen, peer, conditions) {
+            eprintln!("boxes-loopback: {}", e);
+            eprintln!("{}", e.hint());
+            std::process::exit(1);
+        }
+        match boxes.play_loopback(frames) {
+            Some((checksum, rollbacks)) => println!("loopback {} {}", checksum, rollbacks),
+            None => {
+                eprintln!("boxes-loopback: {} stopped playing", peer);
+                std::process::exit(1);
+            },
+        }
+        return;
+    }
+
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            eprintln!("boxes-loopback: {}", e);
+            std::process::exit(1);
+        },
+    };
+    let base = arg("--port").and_then(|t| t.parse::<u16>().ok()).unwrap_or(7101);
+    let addrs = [format!("127.0.0.1:{}", base), format!("127.0.0.1:{}", base + 1)];
+    println!("playing {} frames with {}ms latency and {}% loss", frames, latency, loss);
+    let children = (0..2).map(|i| {
+        let mut command = Command::new(&exe);
+        command.args(["--listen", &addrs[i], "--peer", &addrs[1 - i]])
+            .args(["--frames", &frames.to_string(), "--latency", &latency.to_string()])
+            .args(["--loss", &loss.to_string(), "--seed", &seed.to_string()])
+            .stdout(Stdio::piped());
+        if versus {
+            command.arg("--versus");
+        }
+        command.spawn()
+    }).collect::<Result<Vec<_>, _>>();
+    let children = match children {
+        Ok(children) => children,
+        Err(e) => {
+            eprintln!("boxes-loopback: failed to start a peer: {}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let results = children.into_iter().map(|mut child| {
+        let result = child.stdout.take().and_then(|out| {
+            BufReader::new(out).lines()
+                .map_while(Result::ok)
+                .filter_map(|line| line.strip_prefix("loopback ").map(str::to_string))
+                .last()
+        });
+        let _ = child.wait();
+        result
+    }).collect::<Vec<_>>();
+
+    let mut checksums = vec![];
+    for (i, result) in results.iter().enumerate() {
+        let (checksum, rollbacks) = match result.as_deref().and_then(|r| r.split_once(' ')) {
+            Some(r) => r,
+            None => {
+                eprintln!("boxes-loopback: peer {} didn't finish", i + 1);
+                std::process::exit(1);
+            },
+        };
+        println!("peer {}: checksum {} after {} rollbacks", i + 1, checksum, rollbacks);
+        checksums.push(checksum.to_string());
+    }
+    if checksums[0] != checksums[1] {
+        eprintln!("boxes-loopback: peers desynced");
+        std::process::exit(1);
+    }
+    println!("peers in sync");
+}
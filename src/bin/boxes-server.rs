@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use boxes::{
+    Boxes,
+    Conditions,
+    GameMode,
+    Movement
+};
+
+const SPECTATE_ADDR: &str = "127.0.0.1:7780";
+
+fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    let arg = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1));
+    boxes::set_debug(false);
+    let mut boxes = Boxes::new(800, 800, 1, 10);
+    if let Some(seed) = arg("--seed").and_then(|t| t.parse::<u64>().ok()) {
+        boxes.set_seed(seed);
+    }
+    if let Some(i) = args.iter().position(|a| a == "--territory") {
+        let target = args.get(i + 1).and_then(|t| t.parse::<f64>().ok()).unwrap_or(75.0);
+        boxes.set_mode(GameMode::Territory(target));
+    }
+    if args.iter().any(|a| a == "--versus") {
+        boxes.set_mode(GameMode::Versus);
+    }
+    match arg("--movement").map(|m| m.as_str()) {
+        None | Some("4") => {},
+        Some("8") => boxes.set_movement(Movement::EightWay),
+        Some("analog") => boxes.set_movement(Movement::Analog),
+        Some(m) => println!("unknown movement {}, expected 4, 8 or analog", m),
+    }
+    boxes.set_players(arg("--players").and_then(|t| t.parse::<usize>().ok()).unwrap_or(2));
+    if args.iter().any(|a| a == "--shared-loops") {
+        boxes.set_shared_loops(true);
+    }
+
+    let conditions = Conditions {
+        latency: Duration::from_millis(arg("--latency").and_then(|t| t.parse::<u64>().ok()).unwrap_or(0)),
+        loss: arg("--loss").and_then(|t| t.parse::<f64>().ok()).unwrap_or(0.0) / 100.0,
+    };
+    let spectated = match args.iter().position(|a| a == "--spectate") {
+        Some(i) => {
+            let addr = args.get(i + 1).filter(|a| !a.starts_with("--")).map(|a| a.as_str());
+            boxes.spectate(addr.unwrap_or(SPECTATE_ADDR))
+        },
+        None => Ok(()),
+    };
+    let addr = arg("--bind").map(|a| a.as_str()).unwrap_or("127.0.0.1:7777");
+    if let Err(e) = spectated.and_then(|_| boxes.serve(addr, conditions)) {
+        eprintln!("boxes-server: {}", e);
+        eprintln!("{}", e.hint());
+        std::process::exit(1);
+    }
+}
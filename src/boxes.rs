@@ -1,51 +1,272 @@
+mod animation;
+mod assets;
 mod enemy;
+mod error;
+mod font;
+mod gamepad;
+#[cfg(feature = "gamepad")]
+mod gilrs_input;
+mod highscores;
+mod input;
+mod level;
+mod net;
+mod piston_renderer;
 mod player;
+mod raster;
+mod render;
+mod replay;
+mod rollback;
+mod save;
+mod spectate;
+mod territory;
+mod tui;
+mod view;
+mod viewport;
 
-use std::{
-    cmp,
-    vec
-};
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::vec;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::io;
+use std::time::{Duration, Instant};
 
-use ::image::ImageFormat;
+use ::image::{
+    ImageFormat,
+    RgbaImage
+};
+use glutin::window::Fullscreen;
 use opengl_graphics::OpenGL;
 use piston::Position;
 use piston::input::*;
-use piston::window::WindowSettings;
+use piston::window::{Window, WindowSettings};
 use piston_window::{
-    clear,
     color,
+    Filter,
     PistonWindow,
     Size,
     Texture,
     TextureContext,
     TextureSettings
 };
+use rand::SeedableRng;
+use serde::{
+    Deserialize,
+    Serialize
+};
 use rand::distributions::{Distribution, Uniform};
-use sprite::*;
+use rand_chacha::ChaCha12Rng;
 
+use animation::SpriteSheet;
+use assets::{
+    AssetManager,
+    PackError,
+    PackManifest
+};
 use enemy::{
     Enemy,
+    EnemyDef,
     EnemyState
 };
+use gamepad::Gamepad;
+use input::{
+    Action,
+    Bindings
+};
+use highscores::{
+    HighScore,
+    HighScores
+};
+use net::Remote;
+use level::{
+    Archetype,
+    Level,
+    LevelGenerator
+};
+use piston_renderer::PistonRenderer;
 use player::Player;
+use raster::RasterRenderer;
+use render::Renderer;
+use replay::{
+    Header,
+    InputEvent,
+    Recorder
+};
+use rollback::Peer;
+use save::{
+    positions,
+    PositionDef
+};
+use spectate::Spectators;
+use territory::Territory;
+use view::{
+    CaptureView,
+    PlayerView,
+    SpriteView,
+    View
+};
+use viewport::Viewport;
+
+pub use error::BoxesError;
+pub use gamepad::{
+    GamepadButton,
+    GamepadEvent
+};
+pub use input::Preset;
+pub use net::Conditions;
+pub use player::Movement;
+pub use rollback::Snapshot;
 
-use self::player::Direction;
+static DEBUG: AtomicBool = AtomicBool::new(false);
 
 const SPRITE_WIDTH: i32 = 32;
 const START_X: i32 = SPRITE_WIDTH / 2;
 const START_Y: i32 = SPRITE_WIDTH / 2;
 const MAX_LINE_LEN: f64 = 300.0;
 const WARN_LINE_LEN: f64 = MAX_LINE_LEN * 0.75;
+const OBSTACLE_MARGIN: i32 = SPRITE_WIDTH / 4;
+const OBSTACLE_COLOR: [f32; 4] = [0.25, 0.25, 0.25, 1.0];
+const CLAIMED_COLOR: [f32; 4] = [0.2, 0.4, 0.8, 0.6];
+const HUD_SCALE: f64 = 3.0;
+const TRAIL_COLOR: [f32; 4] = color::RED;
+const DANGER_COLOR: [f32; 4] = color::YELLOW;
+const TRAIL_RADIUS: f64 = 1.5;
+const TRAIL_GRADIENT_STEPS: usize = 8;
+const CAPTURE_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.5];
+const CAPTURE_FLASH: f64 = 0.15;
+const WAYPOINT_SPACING: i32 = SPRITE_WIDTH;
+const WAYPOINT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.8];
+const BINDINGS_FILE: &str = "bindings.json";
+const SAVE_FILE: &str = "quicksave.json";
+const OVERLAY_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.7];
+const SCREENSHOT_TICK: Duration = Duration::from_micros(4167);
+const LIVES: u32 = 3;
+const STUN: Duration = Duration::from_millis(1500);
+const STUNNED_ALPHA: f32 = 0.4;
+// The animations the game plays from each sprite sheet.
+const SHEET_ANIMATIONS: [(&str, &[&str]); 2] = [
+    ("hero.json", &["idle", "walk"]),
+    ("enemy.json", &["idle", "death"]),
+];
+// Sprite tint and trail colour for each player, which also caps how many can play.
+const PLAYER_COLORS: [([f32; 4], [f32; 4]); 4] = [
+    (color::WHITE, TRAIL_COLOR),
+    ([0.6, 1.0, 0.6, 1.0], [0.0, 0.6, 0.0, 1.0]),
+    ([1.0, 0.6, 1.0, 1.0], [0.6, 0.0, 0.8, 1.0]),
+    ([1.0, 1.0, 0.5, 1.0], [0.9, 0.5, 0.0, 1.0]),
+];
 
+// Controls the per tick state dumps on stdout, which are off until the debug key turns
+// them on. Front ends that draw to the terminal keep them off.
+pub fn set_debug(enabled: bool) {
+    DEBUG.store(enabled, Ordering::Relaxed);
+}
+
+fn debug_enabled() -> bool {
+    DEBUG.load(Ordering::Relaxed)
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
 enum GameState {
     Playing,
     LevelComplete,
     PlayerDied,
 }
 
+#[derive(Clone, Deserialize, Serialize)]
+struct Capture {
+    #[serde(with = "positions")]
+    polygon: Vec<Position>,
+    start: Duration,
+}
+
+impl Capture {
+    // Flashes opaque for the first part of the fade, then fades out linearly.
+    fn color(&self, fade: Duration, now: Duration) -> Option<[f32; 4]> {
+        let t = now.saturating_sub(self.start).as_secs_f64() / fade.as_secs_f64().max(f64::EPSILON);
+        if t >= 1.0 {
+            return None;
+        }
+
+        let mut color = CAPTURE_COLOR;
+        if t < CAPTURE_FLASH {
+            color[3] = 0.9;
+        } else {
+            color[3] *= (1.0 - (t - CAPTURE_FLASH) / (1.0 - CAPTURE_FLASH)) as f32;
+        }
+        Some(color)
+    }
+}
+
+// The rebinding screen steps through every action in turn. The game is paused while it is
+// open and `resume` records whether to unpause when it closes.
+struct Rebinding {
+    index: usize,
+    resume: bool,
+}
+
+// Screens shown between games by the windowed and terminal front ends. The game is held
+// rather than paused while one is up, so it never sees them.
+enum Screen {
+    Title,
+    // A score good enough for the table, waiting for its name.
+    NameEntry(HighScore),
+    // The table after a game, with the new score's place if it made it in.
+    GameOver(Option<usize>),
+}
+
+// Everything that belongs to one player rather than the game. Only player one's bindings
+// drive the pause, debug and rebinding actions, and only they can be changed on the
+// rebinding screen.
+#[derive(Clone, Deserialize, Serialize)]
+struct PlayerSlot {
+    player: Player,
+    lines: Vec<Line>,
+    #[serde(skip)]
+    bindings: Bindings,
+    tint: [f32; 4],
+    trail: [f32; 4],
+    lives: u32,
+    caught: u32,
+    stunned: Duration,
+}
+
+impl PlayerSlot {
+    fn new(index: usize, bindings: Bindings, sheet: Rc<SpriteSheet>) -> Self {
+        let start = start_position(index);
+        let (tint, trail) = PLAYER_COLORS[index];
+        Self {
+            player: Player::new(start.x, start.y, SPRITE_WIDTH, SPRITE_WIDTH, sheet),
+            lines: vec![Line { to: start, from: start }],
+            bindings,
+            tint,
+            trail,
+            lives: LIVES,
+            caught: 0,
+            stunned: Duration::ZERO,
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        !self.player.is_dead()
+    }
+}
+
+// Versus plays like capture, but players race each other for enemies and their trails can
+// cut and trap one another.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum GameMode {
+    Capture,
+    Territory(f64),
+    Versus,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
 pub struct Line {
+    #[serde(with = "PositionDef")]
     to: Position,
+    #[serde(with = "PositionDef")]
     from: Position
 }
 
@@ -104,300 +325,1710 @@ impl Line {
       
         false
     }
+
+    // Where the two segments' lines cross, or `None` if they're parallel.
+    fn crossing(&self, line: &Line) -> Option<Position> {
+        let r = ((self.to.x - self.from.x) as f64, (self.to.y - self.from.y) as f64);
+        let s = ((line.to.x - line.from.x) as f64, (line.to.y - line.from.y) as f64);
+        let denominator = r.0 * s.1 - r.1 * s.0;
+        if denominator == 0.0 {
+            return None;
+        }
+
+        let q = ((line.from.x - self.from.x) as f64, (line.from.y - self.from.y) as f64);
+        let t = (q.0 * s.1 - q.1 * s.0) / denominator;
+        Some(Position {
+            x: (self.from.x as f64 + t * r.0).round() as i32,
+            y: (self.from.y as f64 + t * r.1).round() as i32,
+        })
+    }
 }
 
 pub struct Boxes {
-    window_size: Size,
-    lines: Vec<Line>,
-    player: Player,
+    arena_size: Size,
+    players: Vec<PlayerSlot>,
+    shared_loops: bool,
     enemies: Vec<Enemy>,
-    cur_enemies: i32,
-    max_enemies: i32,
+    generator: LevelGenerator,
+    level: Level,
+    seed: u64,
+    rng: ChaCha12Rng,
+    clock: Duration,
+    recorder: Option<Recorder>,
+    remote: Option<Remote>,
+    peer: Option<Peer>,
+    spectators: Option<Spectators>,
+    fullscreen: bool,
+    cursor: Option<[f64; 2]>,
+    bindings_path: PathBuf,
+    save_path: PathBuf,
+    paused: bool,
+    rebinding: Option<Rebinding>,
+    high_scores_path: PathBuf,
+    high_scores: Option<HighScores>,
+    screen: Option<Screen>,
+    gamepad: Gamepad,
+    difficulty: u32,
+    mode: GameMode,
+    territory: Territory,
+    captures: Vec<Capture>,
+    capture_fade: Duration,
+    assets: AssetManager,
+    enemy_sheet: Rc<SpriteSheet>,
+    enemy_defs: HashMap<Archetype, EnemyDef>,
     state: GameState
 }
 
 impl Boxes {
     pub fn new(width: u32, height: u32, start_enemies: i32, max_enemies: i32) -> Self {
-        Self {
-            window_size: Size::from([width, height]),
-            lines: vec![
-                Line{
-                    to: Position { x: START_X, y: START_Y },
-                    from: Position { x: START_X, y: START_Y },
-                }
-            ],
-            cur_enemies: start_enemies,
-            max_enemies: max_enemies,
-            enemies: (0..start_enemies).map(|i| Enemy::new(
-                100 + ((SPRITE_WIDTH * i) + 5),
-                height as i32 - SPRITE_WIDTH,
-                SPRITE_WIDTH, SPRITE_WIDTH,
-                i % 2 == 0
-            )).collect::<Vec<_>>(),
-            player: Player::new(START_X, START_Y, SPRITE_WIDTH, SPRITE_WIDTH),
+        let arena_size = Size::from([width, height]);
+        let generator = LevelGenerator::new(
+            arena_size,
+            Position { x: START_X, y: START_Y },
+            start_enemies,
+            max_enemies
+        );
+        let seed = rand::random::<u64>();
+        let level = generator.generate(seed, 0);
+        let mut assets = AssetManager::new();
+        let hero_sheet = Self::load_sheet(&mut assets, "hero.json");
+        let enemy_sheet = Self::load_sheet(&mut assets, "enemy.json");
+        let enemy_defs = Self::load_enemy_defs(&mut assets);
+        let bindings_path = std::env::var_os("BOXES_BINDINGS")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(BINDINGS_FILE));
+        let mut boxes = Self {
+            arena_size,
+            players: vec![PlayerSlot::new(0, Self::load_bindings(&bindings_path), hero_sheet)],
+            shared_loops: false,
+            enemies: vec![],
+            generator,
+            level,
+            seed,
+            rng: ChaCha12Rng::seed_from_u64(seed),
+            clock: Duration::ZERO,
+            recorder: None,
+            remote: None,
+            peer: None,
+            spectators: None,
+            fullscreen: false,
+            cursor: None,
+            bindings_path,
+            save_path: std::env::var_os("BOXES_SAVE")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(SAVE_FILE)),
+            paused: false,
+            rebinding: None,
+            high_scores_path: HighScores::default_path(),
+            high_scores: None,
+            screen: None,
+            gamepad: Gamepad::new(),
+            difficulty: 0,
+            mode: GameMode::Capture,
+            territory: Territory::new(arena_size),
+            captures: vec![],
+            capture_fade: Duration::from_millis(750),
+            assets,
+            enemy_sheet,
+            enemy_defs,
             state: GameState::Playing
+        };
+        boxes.enemies = boxes.spawn_enemies();
+        boxes
+    }
+
+    // Versus needs someone to play against, so it brings in a second player if needed.
+    pub fn set_mode(&mut self, mode: GameMode) {
+        self.mode = mode;
+        self.territory.reset();
+        if mode == GameMode::Versus && self.players.len() < 2 {
+            self.set_players(2);
+        }
+    }
+
+    // Starts the game over from the first level generated from `seed`.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = ChaCha12Rng::seed_from_u64(seed);
+        self.clock = Duration::ZERO;
+        self.paused = false;
+        self.new_game();
+    }
+
+    // Back to the first level with every player's lives restored.
+    fn new_game(&mut self) {
+        self.difficulty = 0;
+        self.territory.reset();
+        self.captures.clear();
+        for slot in self.players.iter_mut() {
+            slot.player.stop();
+            slot.lives = LIVES;
+            slot.caught = 0;
+            slot.stunned = Duration::ZERO;
+        }
+        self.reset_screen();
+        self.list_level();
+        self.state = GameState::Playing;
+    }
+
+    // Restarts the game and writes its input and tick timing to `path`, so the session
+    // can be re-simulated later.
+    pub fn record(&mut self, path: &Path) -> io::Result<()> {
+        self.set_seed(self.seed);
+        let header = Header {
+            version: replay::VERSION,
+            seed: self.seed,
+            mode: self.mode,
+            movement: self.players[0].player.get_movement(),
+            players: self.players.len(),
+            shared_loops: self.shared_loops,
+        };
+        self.recorder = Some(Recorder::create(path, &header)?);
+        Ok(())
+    }
+
+    pub fn set_movement(&mut self, movement: Movement) {
+        for slot in self.players.iter_mut() {
+            slot.player.set_movement(movement);
+        }
+    }
+
+    pub fn set_players(&mut self, count: usize) {
+        let fewest = if self.mode == GameMode::Versus { 2 } else { 1 };
+        let count = count.clamp(fewest, PLAYER_COLORS.len());
+        let movement = self.players[0].player.get_movement();
+        self.players.truncate(count);
+        while self.players.len() < count {
+            let sheet = Self::load_sheet(&mut self.assets, "hero.json");
+            let mut slot = PlayerSlot::new(self.players.len(), Bindings::from_preset(Preset::default()), sheet);
+            slot.player.set_movement(movement);
+            self.players.push(slot);
+        }
+        self.assign_presets();
+        self.reset_screen();
+    }
+
+    // Players after the first take the key presets player one isn't using, in order.
+    fn assign_presets(&mut self) {
+        let first = self.players[0].bindings.get_preset();
+        let mut presets = Preset::ALL.into_iter().filter(|p| *p != first).cycle();
+        for slot in self.players.iter_mut().skip(1) {
+            slot.bindings = Bindings::from_preset(presets.next().unwrap_or(first));
+        }
+    }
+
+    pub fn player_count(&self) -> usize {
+        self.players.len()
+    }
+
+    // Lets a loop be closed by one player's trail crossing another's twice.
+    pub fn set_shared_loops(&mut self, shared: bool) {
+        self.shared_loops = shared;
+    }
+
+    // Loads key bindings from `path`, which is also where the rebinding screen saves them.
+    pub fn set_bindings_path(&mut self, path: PathBuf) {
+        self.players[0].bindings = Self::load_bindings(&path);
+        self.bindings_path = path;
+        self.assign_presets();
+    }
+
+    // Where the quicksave key writes to and the quickload key reads from.
+    pub fn set_save_path(&mut self, path: PathBuf) {
+        self.save_path = path;
+    }
+
+    // Where the high score table shown between games is kept.
+    pub fn set_high_scores_path(&mut self, path: PathBuf) {
+        self.high_scores_path = path;
+    }
+
+    pub fn set_preset(&mut self, preset: Preset) {
+        self.players[0].bindings = Bindings::from_preset(preset);
+        self.assign_presets();
+    }
+
+    fn is_recorded(action: Action) -> bool {
+        action.is_movement() || action == Action::Pause
+    }
+
+    // Whether a client playing `player` may send `event`. Input from another machine is
+    // only trusted to drive the simulation, and only the host, player one, may pause it
+    // for everyone.
+    fn is_client_input(player: usize, event: InputEvent) -> bool {
+        match event {
+            InputEvent::Pressed(Action::Pause) | InputEvent::Released(Action::Pause) => player == 0,
+            InputEvent::Pressed(action) | InputEvent::Released(action) => action.is_movement(),
+            InputEvent::Waypoint { .. } | InputEvent::ClearWaypoints | InputEvent::Steer { .. } => true,
+        }
+    }
+
+    // Plays the game on the server at `addr` instead of simulating it here.
+    pub fn connect(&mut self, addr: &str, conditions: Conditions) -> Result<(), BoxesError> {
+        let remote = Remote::connect(addr, conditions)
+            .map_err(|source| BoxesError::Network { addr: addr.to_string(), source })?;
+        self.remote = Some(remote);
+        Ok(())
+    }
+
+    // Records input that changes the simulation. When playing on a server it is sent there
+    // instead and true is returned, since only the server's simulation counts. Against a peer
+    // it is held back to be played on a later frame.
+    fn send_input(&mut self, player: usize, event: InputEvent) -> bool {
+        if let Some(remote) = self.remote.as_mut() {
+            remote.send(event);
+            return true;
+        }
+        if let Some(peer) = self.peer.as_mut() {
+            peer.queue(event);
+            return true;
+        }
+
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.input(player, event);
+        }
+        false
+    }
+
+    fn apply_input(&mut self, player: usize, event: InputEvent) {
+        match event {
+            InputEvent::Pressed(action) => self.press_action(player, action),
+            InputEvent::Released(action) => self.release_action(player, action),
+            InputEvent::Waypoint { x, y } => self.add_waypoint(player, Position { x, y }),
+            InputEvent::ClearWaypoints => self.clear_waypoints(player),
+            InputEvent::Steer { heading } => self.steer(player, heading),
+        }
+    }
+
+    // Movement keys are looked up in every player's bindings, the rest only in player one's.
+    fn key_action(&self, key: Key) -> Option<(usize, Action)> {
+        self.players.iter().enumerate().find_map(|(i, slot)| {
+            slot.bindings.action(key)
+                .filter(|a| i == 0 || a.is_movement())
+                .map(|a| (i, a))
+        })
+    }
+
+    // Returns the action the key is bound to, so front ends can handle the ones that
+    // concern them, such as switching to fullscreen.
+    pub fn press_key(&mut self, key: Key) -> Option<Action> {
+        if self.rebinding.is_some() {
+            self.rebind(key);
+            return None;
+        }
+        if self.screen.is_some() {
+            let confirm = key == Key::Return || self.key_action(key) == Some((0, Action::Confirm));
+            self.screen_key(if confirm { Key::Return } else { key });
+            return None;
+        }
+
+        let (player, action) = self.key_action(key)?;
+        self.press_action(player, action);
+        Some(action)
+    }
+
+    pub fn release_key(&mut self, key: Key) {
+        if self.rebinding.is_some() || self.screen.is_some() {
+            return;
+        }
+
+        if let Some((player, action)) = self.key_action(key) {
+            self.release_action(player, action);
+        }
+    }
+
+    // The gamepad drives player one. While the rebinding screen is open it can only step
+    // past actions.
+    pub fn gamepad_event(&mut self, event: GamepadEvent) {
+        // In analog movement the stick steers directly rather than standing in for the d-pad.
+        if let (Movement::Analog, GamepadEvent::Stick { x, y }) = (self.players[0].player.get_movement(), event) {
+            if self.rebinding.is_none() && self.screen.is_none() {
+                self.steer(0, gamepad::stick_heading(x, y));
+            }
+            return;
+        }
+
+        for input in self.gamepad.update(event) {
+            match input {
+                InputEvent::Pressed(Action::Confirm) if self.rebinding.is_some() => self.rebind(Key::Return),
+                InputEvent::Pressed(Action::Confirm) if self.screen.is_some() => self.screen_key(Key::Return),
+                _ if self.rebinding.is_some() || self.screen.is_some() => {},
+                InputEvent::Pressed(action) => self.press_action(0, action),
+                InputEvent::Released(action) => self.release_action(0, action),
+                _ => {},
+            }
+        }
+    }
+
+    // Waypoints are ignored while paused, since the player couldn't see where they lead.
+    fn add_waypoint(&mut self, player: usize, p: Position) {
+        if self.paused {
+            return;
+        }
+
+        if self.send_input(player, InputEvent::Waypoint { x: p.x, y: p.y }) {
+            return;
+        }
+        self.players[player].player.add_waypoint(p);
+    }
+
+    fn clear_waypoints(&mut self, player: usize) {
+        if self.send_input(player, InputEvent::ClearWaypoints) {
+            return;
+        }
+        self.players[player].player.clear_waypoints();
+    }
+
+    // Steers towards `heading` in degrees clockwise from the right, or stops steering.
+    fn steer(&mut self, player: usize, heading: Option<f64>) {
+        if self.paused {
+            return;
+        }
+
+        if self.send_input(player, InputEvent::Steer { heading }) {
+            return;
+        }
+        self.players[player].player.steer(heading);
+    }
+
+    // The mouse drives player one. Clicking starts a new route to the cursor and dragging
+    // extends it, dropping a waypoint each time the cursor has moved far enough from the
+    // last one.
+    fn pointer(&mut self, p: Option<[f64; 2]>, down: bool, dragging: bool) {
+        self.cursor = p;
+        let p = match p {
+            Some(p) => Position { x: p[0].round() as i32, y: p[1].round() as i32 },
+            None => return,
+        };
+        if down {
+            self.clear_waypoints(0);
+            self.add_waypoint(0, p);
+        } else if dragging {
+            let player = &self.players[0].player;
+            let last = match self.remote.as_ref() {
+                Some(remote) => remote.get_last_waypoint().unwrap_or(p),
+                None => player.get_waypoints().back().copied().unwrap_or_else(|| player.get_cur_position()),
+            };
+            if (p.x - last.x).abs() + (p.y - last.y).abs() >= WAYPOINT_SPACING {
+                self.add_waypoint(0, p);
+            }
+        }
+    }
+
+    fn press_action(&mut self, player: usize, action: Action) {
+        if Self::is_recorded(action) && self.send_input(player, InputEvent::Pressed(action)) {
+            return;
+        }
+
+        match action {
+            Action::Pause => self.paused = !self.paused,
+            Action::Debug => set_debug(!debug_enabled()),
+            Action::Rebind => {
+                let resume = !self.paused;
+                if resume {
+                    self.press_action(player, Action::Pause);
+                }
+                self.rebinding = Some(Rebinding { index: 0, resume });
+            },
+            Action::Confirm => {
+                if self.paused && self.rebinding.is_none() {
+                    self.press_action(player, Action::Pause);
+                }
+            },
+            Action::Fullscreen => {},
+            Action::QuickSave => self.quicksave(),
+            Action::QuickLoad => self.quickload(),
+            _ => self.players[player].player.action_pressed(&action),
         }
     }
 
-    fn clear_lines(&mut self) {
-        self.lines.clear();
-        self.player.clear();
-        self.lines.push(Line { to: self.player.get_cur_position(), from: self.player.get_prev_position() });
+    fn release_action(&mut self, player: usize, action: Action) {
+        if Self::is_recorded(action) && self.send_input(player, InputEvent::Released(action)) {
+            return;
+        }
+
+        self.players[player].player.action_released(&action);
+    }
+
+    // Return keeps the current keys for an action and Backspace abandons the changes.
+    // Once every action has been visited the bindings are saved.
+    fn rebind(&mut self, key: Key) {
+        let rebinding = match self.rebinding.as_mut() {
+            Some(r) => r,
+            None => return,
+        };
+        let bindings = &mut self.players[0].bindings;
+        match key {
+            Key::Backspace => {
+                *bindings = Self::load_bindings(&self.bindings_path);
+                self.close_rebinding();
+                return;
+            },
+            Key::Return => {},
+            key => bindings.bind(Action::ALL[rebinding.index], key),
+        }
+
+        rebinding.index += 1;
+        if rebinding.index == Action::ALL.len() {
+            if let Err(e) = bindings.save(&self.bindings_path) {
+                println!("failed to save key bindings to {}: {}", self.bindings_path.display(), e);
+            }
+            self.close_rebinding();
+        }
+    }
+
+    fn close_rebinding(&mut self) {
+        if let Some(rebinding) = self.rebinding.take() {
+            if rebinding.resume {
+                self.press_action(0, Action::Pause);
+            }
+        }
+    }
+
+    // Front ends call this before their first frame. Networked games go straight in, since
+    // the other side isn't waiting on this player's screens.
+    fn show_title(&mut self) {
+        if self.remote.is_some() || self.peer.is_some() {
+            return;
+        }
+        self.high_scores = Some(HighScores::load(&self.high_scores_path));
+        self.screen = Some(Screen::Title);
+    }
+
+    // Called as the last life is lost, before the game starts over. Versus matches have a
+    // winner instead of a score.
+    fn game_over(&mut self) {
+        let scores = match self.high_scores.as_ref() {
+            Some(s) if self.mode != GameMode::Versus => s,
+            _ => return,
+        };
+        let score = HighScore::new(
+            self.difficulty + 1,
+            self.players.iter().map(|slot| slot.caught).sum(),
+            self.seed,
+            self.recorder.as_ref().map(|r| r.get_path().to_path_buf())
+        );
+        self.screen = Some(match scores.rank(&score) {
+            Some(_) => Screen::NameEntry(score),
+            None => Screen::GameOver(None),
+        });
+    }
+
+    // Return moves on from a screen. While a name is being entered Backspace deletes
+    // the last letter, and Return keeps the name, falling back to a placeholder.
+    fn screen_key(&mut self, key: Key) {
+        match (self.screen.take(), key) {
+            (Some(Screen::NameEntry(mut score)), Key::Return) => {
+                if score.name.trim().is_empty() {
+                    score.name = "player".to_string();
+                }
+                let mut rank = None;
+                if let Some(scores) = self.high_scores.as_mut() {
+                    rank = scores.insert(score);
+                    if let Err(e) = scores.save() {
+                        println!("failed to save high scores to {}: {}", scores.get_path().display(), e);
+                    }
+                }
+                self.screen = Some(Screen::GameOver(rank));
+            },
+            (Some(Screen::NameEntry(mut score)), Key::Backspace) => {
+                score.name.pop();
+                self.screen = Some(Screen::NameEntry(score));
+            },
+            (_, Key::Return) => {},
+            (screen, _) => self.screen = screen,
+        }
+    }
+
+    // Typed text, which only matters while a name is being entered. Anything the HUD font
+    // can't draw is left out.
+    fn type_text(&mut self, text: &str) {
+        if let Some(Screen::NameEntry(score)) = self.screen.as_mut() {
+            for c in text.chars().filter(|c| font::has_glyph(*c)) {
+                if score.name.chars().count() < highscores::MAX_NAME {
+                    score.name.push(c.to_ascii_uppercase());
+                }
+            }
+        }
+    }
+
+    fn is_entering_name(&self) -> bool {
+        matches!(self.screen, Some(Screen::NameEntry(_)))
+    }
+
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        self.fullscreen = fullscreen;
+    }
+
+    pub fn set_capture_fade(&mut self, fade: Duration) {
+        self.capture_fade = fade;
+    }
+
+    fn is_safe(&self, p: Position) -> bool {
+        match self.mode {
+            GameMode::Territory(_) => self.territory.is_safe(p) || self.is_boundary(p),
+            GameMode::Capture | GameMode::Versus => false,
+        }
+    }
+
+    fn is_territory_complete(&self) -> bool {
+        match self.mode {
+            GameMode::Territory(target) => self.territory.percentage() >= target,
+            GameMode::Capture | GameMode::Versus => false,
+        }
+    }
+
+    pub fn set_asset_root(&mut self, root: PathBuf) {
+        self.assets.set_root(root);
+        self.reload_assets();
+    }
+
+    pub fn mount_pack(&mut self, path: &Path) -> Result<PackManifest, PackError> {
+        let manifest = self.assets.mount_pack(path)?;
+        self.reload_assets();
+        Ok(manifest)
+    }
+
+    fn reload_assets(&mut self) {
+        self.reload_sheets();
+        self.enemy_defs = Self::load_enemy_defs(&mut self.assets);
+        self.reset_screen();
+    }
+
+    // Definitions missing from an overriding enemies.json keep their built in values.
+    fn load_enemy_defs(assets: &mut AssetManager) -> HashMap<Archetype, EnemyDef> {
+        let json = assets::embedded("enemies.json").unwrap_or_default();
+        let mut defs: HashMap<Archetype, EnemyDef> = serde_json::from_slice(json).expect("invalid built in enemy definitions");
+        let json = assets.load("enemies.json").unwrap_or_default();
+        match serde_json::from_slice::<HashMap<Archetype, EnemyDef>>(&json) {
+            Ok(loaded) => defs.extend(loaded),
+            Err(e) => println!("invalid enemy definitions: {}, using built in copy", e),
+        }
+
+        defs
+    }
+
+    fn load_level(&mut self) -> Level {
+        let name = format!("levels/{}.json", self.difficulty);
+        if let Some(json) = self.assets.load(&name) {
+            match serde_json::from_slice::<Level>(&json) {
+                Ok(level) => return Level { seed: self.seed, difficulty: self.difficulty, ..level },
+                Err(e) => println!("invalid level {}: {}, generating one instead", name, e),
+            }
+        }
+
+        self.generator.generate(self.seed, self.difficulty)
+    }
+
+    // A sheet missing any of the animations the game plays is replaced by the built in one
+    // as a whole, rather than leaving sprites stuck waiting on an animation that never ends.
+    fn load_sheet(assets: &mut AssetManager, name: &str) -> Rc<SpriteSheet> {
+        let json = assets.load(name).unwrap_or_default();
+        let required = SHEET_ANIMATIONS.iter().find(|(n, _)| *n == name).map(|(_, a)| *a).unwrap_or_default();
+        let sheet = SpriteSheet::from_json(&String::from_utf8_lossy(&json))
+            .map_err(|e| e.to_string())
+            .and_then(|sheet| match sheet.missing(required) {
+                Some(animation) => Err(format!("no {} animation", animation)),
+                None => Ok(sheet),
+            });
+        match sheet {
+            Ok(sheet) => Rc::new(sheet),
+            Err(e) => {
+                println!("invalid sprite sheet {}: {}, using built in copy", name, e);
+                let json = assets::embedded(name).unwrap_or_default();
+                Rc::new(SpriteSheet::from_json(&String::from_utf8_lossy(json)).expect("invalid built in sprite sheet"))
+            },
+        }
+    }
+
+    // A broken bindings file is reported with how to fix it, and the default keys used.
+    fn load_bindings(path: &Path) -> Bindings {
+        Bindings::load(path).unwrap_or_else(|e| {
+            println!("{}, using the default keys", e);
+            println!("{}", e.hint());
+            Bindings::default()
+        })
+    }
+
+    fn reload_sheets(&mut self) {
+        for i in 0..self.players.len() {
+            let sheet = Self::load_sheet(&mut self.assets, "hero.json");
+            self.players[i].player.set_sprite_sheet(sheet);
+        }
+        self.enemy_sheet = Self::load_sheet(&mut self.assets, "enemy.json");
+        for e in self.enemies.iter_mut() {
+            e.set_sprite_sheet(self.enemy_sheet.clone());
+        }
+    }
+
+    fn load_image(&mut self, name: &str) -> Result<RgbaImage, BoxesError> {
+        let bytes = self.assets.load(name).unwrap_or_default();
+        match ::image::load_from_memory_with_format(&bytes, ImageFormat::Png) {
+            Ok(img) => Ok(img.to_rgba8()),
+            Err(e) => {
+                println!("failed to load {}: {}, using built in copy", name, e);
+                ::image::load_from_memory_with_format(assets::embedded(name).unwrap_or_default(), ImageFormat::Png)
+                    .map(|img| img.to_rgba8())
+                    .map_err(|source| BoxesError::Image { name: name.to_string(), source })
+            },
+        }
+    }
+
+    // Tries the newest OpenGL version first and falls back to older ones, since some
+    // drivers and virtual machines only provide a 3.0 or 2.1 context. The windowing
+    // backend panics rather than returning an error when there is no display at all.
+    fn open_window(&self) -> Result<PistonWindow, BoxesError> {
+        let mut attempts = vec![];
+        for opengl in [OpenGL::V3_2, OpenGL::V3_1, OpenGL::V3_0, OpenGL::V2_1] {
+            let settings = WindowSettings::new("Boxes", self.arena_size)
+                .exit_on_esc(true)
+                .resizable(true)
+                .fullscreen(self.fullscreen)
+                .graphics_api(opengl);
+            let hook = panic::take_hook();
+            panic::set_hook(Box::new(|_| {}));
+            let result = panic::catch_unwind(AssertUnwindSafe(|| settings.build::<PistonWindow>()));
+            panic::set_hook(hook);
+
+            match result {
+                Ok(Ok(window)) => return Ok(window),
+                Ok(Err(e)) => {
+                    println!("failed to create an OpenGL {:?} window: {}", opengl, e);
+                    attempts.push(format!("OpenGL {:?}: {}", opengl, e));
+                },
+                Err(payload) => {
+                    let reason = payload.downcast_ref::<String>().cloned()
+                        .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+                        .unwrap_or_default();
+                    return Err(BoxesError::NoDisplay(reason));
+                },
+            }
+        }
+
+        Err(BoxesError::Window(attempts))
+    }
+
+    fn spawn_enemies(&self) -> Vec<Enemy> {
+        self.level.enemies.iter().map(|s| Enemy::new(
+            s.position.x,
+            s.position.y,
+            SPRITE_WIDTH,
+            SPRITE_WIDTH,
+            &self.enemy_defs[&s.archetype],
+            self.enemy_sheet.clone()
+        )).collect::<Vec<_>>()
+    }
+
+    fn clear_lines(&mut self, i: usize) {
+        let slot = &mut self.players[i];
+        slot.lines.clear();
+        slot.player.clear();
+        slot.lines.push(Line { to: slot.player.get_cur_position(), from: slot.player.get_prev_position() });
+    }
+
+    // Said once as each level starts, so a level worth looking at again can be regenerated.
+    fn list_level(&self) {
+        println!("level: {} seed: {} obstacles: {}", self.level.difficulty, self.level.seed, self.level.obstacles.len());
     }
 
     fn list_state(&mut self) {
         println!("====================");
-        self.player.list_state();
-        println!("lines:");
-        for l in self.lines.iter() {
-            println!("  {},{} {},{}", l.from.x, l.from.y, l.to.x, l.to.y);
+        for (i, slot) in self.players.iter().enumerate() {
+            println!("player {} lives: {} caught: {}", i + 1, slot.lives, slot.caught);
+            slot.player.list_state();
+            println!("lines:");
+            for l in slot.lines.iter() {
+                println!("  {},{} {},{}", l.from.x, l.from.y, l.to.x, l.to.y);
+            }
+        }
+        if let Some(c) = self.cursor {
+            println!("cursor: {:.0},{:.0}", c[0], c[1]);
         }
         println!("====================");
     }
 
-    fn update_player(&mut self) {
-        let mut player_pos = self.player.get_cur_position();
-        let margin = SPRITE_WIDTH / 4;
-        player_pos.x = player_pos.x + margin;
-        player_pos.y = player_pos.y - margin;
-        for e in self.enemies.iter().filter(|e| e.is_alive()) {
-            if self.player.collided(e) {
-                self.player.dead();
+    // Dying costs a life and keeps the player out until the screen is reset, except in
+    // versus, where they are sent back to the start straight away. Stunned players can't
+    // be caught.
+    fn update_player(&mut self, i: usize) {
+        let safe = self.is_safe(self.players[i].player.get_cur_position()) || !self.players[i].stunned.is_zero();
+        if !safe && self.enemies.iter().filter(|e| e.is_alive()).any(|e| self.players[i].player.collided(e)) {
+            if self.mode == GameMode::Versus {
+                self.knock_out(i);
+                return;
             }
+            let slot = &mut self.players[i];
+            slot.player.dead();
+            slot.lives = slot.lives.saturating_sub(1);
         }
 
-        if self.player.is_dead() {
+        let slot = &mut self.players[i];
+
+        if slot.player.is_dead() {
+            self.clear_lines(i);
             return
         }
 
-        if self.player.is_moving() {
-            if let Some(l) = self.lines.last_mut() {
-                if l.len() > MAX_LINE_LEN {
-                    self.clear_lines();
+        // Territory trails only end back on safe ground, however far that is.
+        let territory = matches!(self.mode, GameMode::Territory(_));
+        if slot.player.is_moving() {
+            if let Some(l) = slot.lines.last_mut() {
+                if l.len() > MAX_LINE_LEN && !territory {
+                    self.clear_lines(i);
                 } else {
-                    if self.player.changed_axis() {
-                        self.lines.push(Line{
-                            to: self.player.get_cur_position(),
-                            from: self.player.get_prev_position()
+                    if slot.player.changed_axis() {
+                        slot.lines.push(Line{
+                            to: slot.player.get_cur_position(),
+                            from: slot.player.get_prev_position()
                         })
                     } else {
-                        l.to = self.player.get_cur_position();
+                        l.to = slot.player.get_cur_position();
                     }
                 }
             }
         }
     }
 
-    fn update_enemies(&mut self) {
-        if self.lines.len() >= 4 {
-            if let Some(last_line) = self.lines.last() {
-                if let Some(first_line) = self.lines.first() {
-                    if last_line.intersects(first_line) {
-                        let x_coords = vec!(
-                                first_line.from.x,
-                            first_line.to.x,
-                            last_line.from.x,
-                            last_line.to.x
-                        );
-                        let y_coords = vec!(
-                                first_line.from.y,
-                            first_line.to.y,
-                            last_line.from.y,
-                            last_line.to.y
-                        );
-                        if let Some(start_x) = x_coords.iter().min() {
-                            if let Some(start_y) = y_coords.iter().min() {
-                                if let Some(end_x) = x_coords.iter().max() {
-                                    if let Some(end_y) = y_coords.iter().max() {
-                                        for e in self.enemies.iter_mut().filter(|e| e.is_alive()) {
-                                            let e_pos = e.get_position();
-                                            if (*start_x <= e_pos.x) && (e_pos.x <= *end_x) && (*start_y <= e_pos.y) && (e_pos.y <= *end_y) {
-                                                e.dead();
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        self.clear_lines();
-                    }
+    fn is_blocked(&self, p: Position) -> bool {
+        self.level.obstacles.iter().any(|o| o.contains(p, OBSTACLE_MARGIN))
+    }
+
+    fn is_boundary(&self, p: Position) -> bool {
+        self.level.obstacles.iter().any(|o| o.contains(p, OBSTACLE_MARGIN + 1))
+    }
+
+    // The trail closes either by crossing one of its earlier segments or by leaving one
+    // obstacle and returning to one, in which case the obstacle supplies the missing side.
+    // Axis-aligned trails need four segments to cross themselves, angled ones only three.
+    fn closed_polygon(&self, i: usize) -> Option<Vec<Position>> {
+        let slot = &self.players[i];
+        let lines = &slot.lines;
+        let first_line = lines.first()?;
+        let last_line = lines.last()?;
+        let min_loop = match slot.player.get_movement() {
+            Movement::Cardinal => 4,
+            Movement::EightWay | Movement::Analog => 3,
+        };
+        let crossed = lines.len().checked_sub(min_loop - 1)
+            .and_then(|end| lines[..end].iter().position(|l| last_line.intersects(l)));
+        if let Some(i) = crossed {
+            let mut polygon = vec![last_line.crossing(&lines[i]).unwrap_or(lines[i].from)];
+            polygon.extend(lines[i..lines.len() - 1].iter().map(|l| l.to));
+            Some(polygon)
+        } else if lines.len() >= 2 && self.is_boundary(first_line.from) && self.is_boundary(last_line.to) {
+            let mut polygon = vec![first_line.from];
+            polygon.extend(lines.iter().map(|l| l.to));
+            Some(polygon)
+        } else {
+            None
+        }
+    }
+
+    // With shared loops a player's newest segment crossing another player's trail, which
+    // one of their earlier segments also crosses, closes the loop between the two
+    // crossings: along this player's trail and back along the other's. Returns the loop
+    // and whose trail it used.
+    fn shared_polygon(&self, i: usize) -> Option<(Vec<Position>, usize)> {
+        if !self.shared_loops {
+            return None;
+        }
+
+        let lines = &self.players[i].lines;
+        let last_line = lines.last()?;
+        for (other, slot) in self.players.iter().enumerate().filter(|(o, s)| *o != i && s.is_alive()) {
+            let theirs = &slot.lines;
+            let end = match theirs.iter().position(|l| l.intersects(last_line)) {
+                Some(end) => end,
+                None => continue,
+            };
+            let crossing = lines[..lines.len() - 1].iter().enumerate().find_map(|(start, l)| {
+                theirs.iter().position(|t| t.intersects(l)).map(|from| (start, from))
+            });
+            let (start, from) = match crossing {
+                Some(c) => c,
+                None => continue,
+            };
+
+            let mut polygon = vec![lines[start].crossing(&theirs[from]).unwrap_or(lines[start].to)];
+            polygon.extend(lines[start..lines.len() - 1].iter().map(|l| l.to));
+            polygon.push(last_line.crossing(&theirs[end]).unwrap_or(last_line.to));
+            if end > from {
+                polygon.extend(theirs[from + 1..=end].iter().rev().map(|l| l.from));
+            } else {
+                polygon.extend(theirs[end..from].iter().map(|l| l.to));
+            }
+            return Some((polygon, other));
+        }
+
+        None
+    }
+
+    // Costs the player a life and, if they have any left, sends them back to the start
+    // stunned for a moment.
+    fn knock_out(&mut self, i: usize) {
+        let start = start_position(i);
+        let slot = &mut self.players[i];
+        slot.lives = slot.lives.saturating_sub(1);
+        slot.player.stop();
+        if slot.lives == 0 {
+            slot.player.dead();
+        } else {
+            slot.player.reset(start.x, start.y);
+            slot.stunned = STUN;
+        }
+        self.clear_lines(i);
+    }
+
+    // In versus, the ground a player covered this tick cuts any opponent's trail it
+    // crosses, leaving them only the part between the cut and where they are now.
+    fn cut_trails(&mut self, i: usize) {
+        let player = &self.players[i].player;
+        let step = Line { from: player.get_prev_position(), to: player.get_cur_position() };
+        if step.from == step.to {
+            return;
+        }
+
+        for (o, slot) in self.players.iter_mut().enumerate() {
+            if o == i || !slot.is_alive() {
+                continue;
+            }
+            if let Some(k) = slot.lines.iter().rposition(|l| l.intersects(&step)) {
+                let cut = slot.lines[k].crossing(&step).unwrap_or(slot.lines[k].to);
+                slot.lines.drain(..k);
+                slot.lines[0].from = cut;
+            }
+        }
+    }
+
+    // Cardinal trails capture the whole box around the loop, as they always have; angled
+    // ones capture just the area inside it. The enemies caught are credited to the player
+    // who closed the loop, and in versus any opponent inside it is knocked out.
+    fn update_enemies(&mut self, i: usize) {
+        let (polygon, partner) = match self.closed_polygon(i) {
+            Some(polygon) => (polygon, None),
+            None => match self.shared_polygon(i) {
+                Some((polygon, other)) => (polygon, Some(other)),
+                None => return,
+            },
+        };
+
+        let cardinal = self.players[i].player.get_movement() == Movement::Cardinal;
+        let polygon = if cardinal { bounding_box(&polygon) } else { polygon };
+        let inside = |p: Position| if cardinal {
+            let (start, end) = (polygon[0], polygon[2]);
+            (start.x <= p.x) && (p.x <= end.x) && (start.y <= p.y) && (p.y <= end.y)
+        } else {
+            territory::contains(&polygon, (p.x as f64, p.y as f64))
+        };
+        for e in self.enemies.iter_mut().filter(|e| e.is_alive()) {
+            if inside(e.get_position()) {
+                e.dead();
+                self.players[i].caught += 1;
+            }
+        }
+        if self.mode == GameMode::Versus {
+            let trapped = (0..self.players.len())
+                .filter(|o| *o != i && self.players[*o].is_alive() && inside(self.players[*o].player.get_cur_position()))
+                .collect::<Vec<_>>();
+            for o in trapped {
+                self.knock_out(o);
+            }
+        }
+        self.captures.push(Capture { polygon, start: self.clock });
+        self.clear_lines(i);
+        if let Some(other) = partner {
+            self.clear_lines(other);
+        }
+    }
+
+    // In territory mode the trail starts whenever the player leaves safe ground, so
+    // returning to it closes off the ground between the trail and the safe edge.
+    fn update_territory(&mut self, i: usize) {
+        if !self.is_safe(self.players[i].player.get_cur_position()) {
+            return;
+        }
+
+        let lines = &self.players[i].lines;
+        let mut trail = lines.first().map(|l| vec![l.from]).unwrap_or_default();
+        trail.extend(lines.iter().map(|l| l.to));
+        if trail.iter().any(|p| !self.is_safe(*p)) {
+            let outside = self.enemies.iter().map(|e| !self.territory.is_claimed(e.get_position())).collect::<Vec<_>>();
+            let claimed = self.territory.enclose(&trail);
+            for (e, outside) in self.enemies.iter_mut().zip(outside) {
+                if outside && e.is_alive() && self.territory.is_claimed(e.get_position()) {
+                    e.dead();
+                    self.players[i].caught += 1;
                 }
             }
+            for [x, y, w, h] in claimed {
+                let polygon = bounding_box(&[
+                    Position { x: x as i32, y: y as i32 },
+                    Position { x: (x + w) as i32, y: (y + h) as i32 }
+                ]);
+                self.captures.push(Capture { polygon, start: self.clock });
+            }
         }
+        self.clear_lines(i);
     }
 
+    // Each enemy goes after whichever living player is closest to it.
     fn move_enemies(&mut self) {
+        let attack = Uniform::from(0..3);
         for e in self.enemies.iter_mut() {
+            let p = e.get_position();
+            let target = self.players.iter()
+                .filter(|slot| slot.is_alive())
+                .map(|slot| &slot.player)
+                .min_by_key(|player| {
+                    let q = player.get_cur_position();
+                    (p.x - q.x).pow(2) + (p.y - q.y).pow(2)
+                });
+            let target = match target {
+                Some(t) => t,
+                None => continue,
+            };
             if e.is_aggressive() {
-                e.move_toward_player(&self.player, self.window_size);
+                e.move_toward_player(target, self.arena_size, &self.level.obstacles);
                 continue;
             }
-            let mut range = rand::thread_rng();
-            let attack = Uniform::from(0..3);
-            match attack.sample(&mut range) {
-                0 => {e.move_away_from_player(&self.player, self.window_size)},
+            match attack.sample(&mut self.rng) {
+                0 => {e.move_away_from_player(target, self.arena_size, &self.level.obstacles)},
                 1 => {},
-                _ => {e.move_toward_player(&self.player, self.window_size)},
+                _ => {e.move_toward_player(target, self.arena_size, &self.level.obstacles)},
             }
         }
     }
 
-    fn update(&mut self) {
-        self.update_player();
+    // Angled trails bend in smaller steps, so they keep more segments to close a loop with.
+    fn max_trail(&self, i: usize) -> usize {
+        match self.players[i].player.get_movement() {
+            Movement::Cardinal => 4,
+            Movement::EightWay | Movement::Analog => 32,
+        }
+    }
+
+    fn update(&mut self, dt: Duration) {
+        for i in 0..self.players.len() {
+            if !self.players[i].is_alive() {
+                continue;
+            }
 
-        self.update_enemies();
+            self.update_player(i);
+            match self.mode {
+                GameMode::Capture | GameMode::Versus => {
+                    if self.mode == GameMode::Versus {
+                        self.cut_trails(i);
+                    }
+                    self.update_enemies(i);
 
-        if self.lines.len() > 4 {
-            self.lines.remove(0);
+                    if self.players[i].lines.len() > self.max_trail(i) {
+                        self.players[i].lines.remove(0);
+                    }
+                },
+                GameMode::Territory(_) => self.update_territory(i),
+            }
         }
 
         self.move_enemies();
 
-        self.list_state();
+        self.update_animations(dt);
+
+        if debug_enabled() {
+            self.list_state();
+        }
     }
 
-    pub fn reset_screen(&mut self) {
-        self.enemies = (0..self.cur_enemies).map(|i| Enemy::new(
-            100 + ((SPRITE_WIDTH * i) + 5),
-            self.window_size.height as i32 - SPRITE_WIDTH,
-            SPRITE_WIDTH,
-            SPRITE_WIDTH,
-            i % 2 == 0
-        )).collect::<Vec<_>>();
-        self.player.reset(START_X, START_Y);
-        self.clear_lines();
+    // Advances the game by one tick of `dt` game time, independent of any window or front
+    // end. Given the same seed, ticks and input the game always plays out the same way.
+    #[doc(hidden)]
+    pub fn step(&mut self, dt: Duration) {
+        if let Some(remote) = self.remote.as_mut() {
+            remote.poll();
+            self.paused = remote.get_view().is_some_and(|v| v.paused);
+            return;
+        }
+        // The peer runs the game in fixed frames of its own, calling back in here for each.
+        if let Some(mut peer) = self.peer.take() {
+            peer.advance(self, dt);
+            self.peer = Some(peer);
+            return;
+        }
+
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(e) = recorder.tick(dt) {
+                println!("failed to record: {}, recording stopped", e);
+                self.recorder = None;
+            }
+        }
+
+        if self.paused {
+            return;
+        }
+
+        self.clock += dt;
+        match self.state {
+            GameState::LevelComplete => {
+                self.difficulty += 1;
+                self.territory.reset();
+                self.reset_screen();
+                self.list_level();
+                self.state = GameState::Playing;
+            },
+            // Once every player is out of lives the game starts over.
+            GameState::PlayerDied => {
+                if self.players.iter().all(|slot| slot.lives == 0) {
+                    println!("game over");
+                    self.game_over();
+                    self.new_game();
+                } else {
+                    self.reset_screen();
+                }
+                self.state = GameState::Playing;
+            },
+            GameState::Playing => {
+                for i in 0..self.players.len() {
+                    if !self.players[i].stunned.is_zero() {
+                        self.players[i].stunned = self.players[i].stunned.saturating_sub(dt);
+                    } else if self.players[i].is_alive() {
+                        let player = self.players[i].player.update(self, dt);
+                        self.players[i].player = player;
+                    }
+                }
+                self.update(dt);
+
+                if !self.players.iter().any(|slot| slot.is_alive()) {
+                    self.state = GameState::PlayerDied;
+                }
+
+                // A versus match is over once only one player has lives left.
+                let standing = self.players.iter().filter(|slot| slot.lives > 0).count();
+                if self.mode == GameMode::Versus && standing == 1 {
+                    if let Some(winner) = self.players.iter().position(|slot| slot.lives > 0) {
+                        println!("player {} wins", winner + 1);
+                    }
+                    self.new_game();
+                }
+            },
+        }
     }
 
-    pub fn run(&mut self) {
-        let opengl = OpenGL::V3_2;
+    fn update_animations(&mut self, dt: Duration) {
+        let (fade, now) = (self.capture_fade, self.clock);
+        self.captures.retain(|c| c.color(fade, now).is_some());
 
-        let mut window: PistonWindow = WindowSettings::new("Boxes", self.window_size)
-            .exit_on_esc(true)
-            .graphics_api(opengl)
-            .build()
-            .unwrap();
-    
+        for e in self.enemies.iter_mut() {
+            e.update(dt);
+        }
+        self.enemies.retain(|e| e.get_state() != EnemyState::Done);
+
+        if self.enemies.is_empty() || self.is_territory_complete() {
+            self.enemies.clear();
+            self.state = GameState::LevelComplete;
+        }
+    }
+
+    pub fn view(&self) -> View {
+        let point = |p: Position| [p.x as f64, p.y as f64];
+        let players = self.players.iter().map(|slot| {
+            let mut tint = slot.tint;
+            if !slot.stunned.is_zero() {
+                tint[3] *= STUNNED_ALPHA;
+            }
+            PlayerView {
+                trail: slot.trail,
+                lines: slot.lines.iter().map(|l| [l.from.x as f64, l.from.y as f64, l.to.x as f64, l.to.y as f64]).collect(),
+                waypoints: slot.player.get_waypoints().iter().map(|w| point(*w)).collect(),
+                sprite: (slot.lives > 0 || slot.is_alive()).then(|| SpriteView {
+                    src_rect: slot.player.get_src_rect(),
+                    position: point(slot.player.get_cur_position()),
+                    rotation: slot.player.get_facing(),
+                    tint,
+                }),
+                lives: slot.lives,
+                caught: slot.caught,
+            }
+        }).collect();
+
+        View {
+            arena: [self.arena_size.width, self.arena_size.height],
+            claimed: self.territory.runs(),
+            obstacles: self.level.obstacles.iter()
+                .map(|o| [o.x as f64, o.y as f64, o.width as f64, o.height as f64])
+                .collect(),
+            captures: self.captures.iter()
+                .filter_map(|c| c.color(self.capture_fade, self.clock).map(|color| CaptureView {
+                    color,
+                    polygon: c.polygon.iter().map(|p| point(*p)).collect(),
+                }))
+                .collect(),
+            players,
+            enemies: self.enemies.iter().map(|e| SpriteView {
+                src_rect: e.get_src_rect(),
+                position: point(e.get_position()),
+                rotation: 0.0,
+                tint: color::WHITE,
+            }).collect(),
+            territory: match self.mode {
+                GameMode::Territory(target) => Some([self.territory.percentage(), target]),
+                GameMode::Capture | GameMode::Versus => None,
+            },
+            paused: self.paused,
+        }
+    }
+
+    pub fn draw<R: Renderer>(&self, renderer: &mut R) {
+        match self.remote.as_ref().map(|r| r.get_view()) {
+            Some(Some(view)) => view.draw(renderer),
+            Some(None) => {
+                renderer.clear(color::GRAY);
+                let text = "connecting";
+                let x = (self.arena_size.width - font::text_width(text) as f64 * HUD_SCALE) / 2.0;
+                renderer.draw_text(color::WHITE, text, [x, self.arena_size.height / 2.0], HUD_SCALE);
+            },
+            None => self.view().draw(renderer),
+        }
+        // Shown until the server manages to send a snapshot again.
+        if let Some(error) = self.remote.as_ref().and_then(|r| r.get_error()) {
+            let x = (self.arena_size.width - font::text_width(error) as f64 * HUD_SCALE) / 2.0;
+            renderer.draw_text(color::YELLOW, error, [x.max(0.0), font::GLYPH_HEIGHT as f64 * HUD_SCALE], HUD_SCALE);
+        }
+
+        if self.screen.is_some() {
+            self.draw_screen(renderer);
+        } else if self.rebinding.is_some() || self.paused {
+            self.draw_overlay(renderer);
+        }
+    }
+
+    fn draw_screen<R: Renderer>(&self, renderer: &mut R) {
+        renderer.draw_rect(OVERLAY_COLOR, [0.0, 0.0, self.arena_size.width, self.arena_size.height]);
+        let line_height = (font::GLYPH_HEIGHT * 2) as f64 * HUD_SCALE;
+        let (title, highlight, footer) = match &self.screen {
+            Some(Screen::Title) => ("boxes".to_string(), None, "press return to play"),
+            Some(Screen::NameEntry(score)) => (format!("new high score: {}_", score.name), None, "type your name - return saves"),
+            Some(Screen::GameOver(rank)) => ("game over".to_string(), *rank, "press return to play again"),
+            None => return,
+        };
+
+        let mut lines = vec![(title, color::WHITE), (String::new(), color::WHITE)];
+        let scores = self.high_scores.as_ref().map(|s| s.get_scores()).unwrap_or_default();
+        if scores.is_empty() {
+            lines.push(("no high scores yet".to_string(), color::WHITE));
+        }
+        for (i, score) in scores.iter().enumerate() {
+            let color = if highlight == Some(i) { color::YELLOW } else { color::WHITE };
+            lines.push((
+                format!("{:>2}. {:<12} level {:<3} caught {:<4} {}", i + 1, score.name, score.level, score.caught, score.get_date()),
+                color
+            ));
+        }
+        if let Some(score) = highlight.and_then(|i| scores.get(i)) {
+            lines.push((String::new(), color::WHITE));
+            let replay = score.replay.as_ref().map(|p| format!(" - replay {}", p.display())).unwrap_or_default();
+            lines.push((format!("seed {}{}", score.seed, replay), color::YELLOW));
+        }
+        lines.push((String::new(), color::WHITE));
+        lines.push((footer.to_string(), color::WHITE));
+
+        let top = (self.arena_size.height - lines.len() as f64 * line_height) / 2.0;
+        for (i, (text, color)) in lines.iter().enumerate() {
+            renderer.draw_text(*color, text, [line_height, top + i as f64 * line_height], HUD_SCALE);
+        }
+    }
+
+    fn draw_overlay<R: Renderer>(&self, renderer: &mut R) {
+        renderer.draw_rect(OVERLAY_COLOR, [0.0, 0.0, self.arena_size.width, self.arena_size.height]);
+        let line_height = (font::GLYPH_HEIGHT * 2) as f64 * HUD_SCALE;
+        let rebinding = match &self.rebinding {
+            Some(r) => r,
+            None => {
+                let text = "paused";
+                let x = (self.arena_size.width - font::text_width(text) as f64 * HUD_SCALE) / 2.0;
+                renderer.draw_text(color::WHITE, text, [x, self.arena_size.height / 2.0], HUD_SCALE);
+                return;
+            },
+        };
+
+        let mut lines = vec![("press a key for each action".to_string(), color::WHITE), (String::new(), color::WHITE)];
+        for (i, action) in Action::ALL.iter().enumerate() {
+            let keys = self.players[0].bindings.keys(*action).iter().map(|k| format!("{:?}", k)).collect::<Vec<_>>();
+            let (marker, color) = if i == rebinding.index { ("> ", color::YELLOW) } else { ("  ", color::WHITE) };
+            lines.push((format!("{}{}: {}", marker, action.label(), keys.join(" / ")), color));
+        }
+        lines.push((String::new(), color::WHITE));
+        lines.push(("return keeps - backspace cancels".to_string(), color::WHITE));
+
+        let top = (self.arena_size.height - lines.len() as f64 * line_height) / 2.0;
+        for (i, (text, color)) in lines.iter().enumerate() {
+            renderer.draw_text(*color, text, [line_height, top + i as f64 * line_height], HUD_SCALE);
+        }
+    }
+
+    fn raster_renderer(&mut self) -> Result<RasterRenderer, BoxesError> {
+        Ok(RasterRenderer::new(
+            self.arena_size.width as u32,
+            self.arena_size.height as u32,
+            self.load_image("hero.png")?,
+            self.load_image("enemy.png")?
+        ))
+    }
+
+    // Advances the game `ticks` times at roughly the windowed tick rate, without a window,
+    // and saves the resulting frame.
+    pub fn screenshot(&mut self, path: &Path, ticks: u32) -> Result<(), BoxesError> {
+        for _ in 0..ticks {
+            self.step(SCREENSHOT_TICK);
+        }
+
+        let mut renderer = self.raster_renderer()?;
+        self.draw(&mut renderer);
+        renderer.into_frame()
+            .save(path)
+            .map_err(|source| BoxesError::Save { path: path.to_path_buf(), source })
+    }
+
+    pub fn reset_screen(&mut self) {
+        self.level = self.load_level();
+        self.enemies = self.spawn_enemies();
+        for i in 0..self.players.len() {
+            if self.players[i].lives > 0 {
+                let start = start_position(i);
+                self.players[i].player.reset(start.x, start.y);
+            }
+            self.clear_lines(i);
+        }
+    }
+
+    pub fn run(&mut self) -> Result<(), BoxesError> {
+        let mut window = self.open_window()?;
         let factory = window.factory.clone();
         let mut texture_context = TextureContext {
-            factory: factory,
+            factory,
             encoder: window.factory.create_command_buffer().into()
         };
     
-        let image_buffer = match ::image::load_from_memory_with_format(include_bytes!("../images/hero.png"), ImageFormat::Png) {
-            Ok(img) => img,
-            Err(_) => panic!("failed to load hero sprite"),
+        let mut create_texture = |name: &str, image: Result<RgbaImage, BoxesError>| {
+            // Nearest filtering keeps the pixel art sharp when the arena is scaled up.
+            Texture::from_image(&mut texture_context, &image?, &TextureSettings::new().filter(Filter::Nearest))
+                .map(Rc::new)
+                .map_err(|e| BoxesError::Texture { name: name.to_string(), reason: e.to_string() })
         };
-    
-        let player_texture = Rc::new(Texture::from_image(
-            &mut texture_context,
-            &image_buffer.to_rgba8(),
-            &TextureSettings::new()
-        ).unwrap());
-    
-        let mut player_sprite = Sprite::from_texture(player_texture);
 
-        let image_buffer = match ::image::load_from_memory_with_format(include_bytes!("../images/enemy.png"), ImageFormat::Png) {
-            Ok(img) => img,
-            Err(_) => panic!("failed to load enemy sprite"),
-        };
-        let enemy_texture = Rc::new(Texture::from_image(
-            &mut texture_context,
-            &image_buffer.to_rgba8(),
-            &TextureSettings::new()
-        ).unwrap());
-
-        let mut enemy_sprites = self.enemies.iter().map(|_| Sprite::from_texture(enemy_texture.to_owned())).collect::<Vec<_>>();
+        let mut hero_texture = create_texture("hero.png", self.load_image("hero.png"))?;
+        let mut enemy_texture = create_texture("enemy.png", self.load_image("enemy.png"))?;
+        let mut last_step = Instant::now();
+        let mut dragging = false;
+        #[cfg(feature = "gamepad")]
+        let mut gamepads = gilrs_input::GilrsInput::new();
+        self.show_title();
 
         while let Some(e) = window.next() {
-            match self.state {
-                GameState::LevelComplete => {
-                    self.cur_enemies = cmp::min(self.cur_enemies + 1, self.max_enemies);
-                    self.reset_screen();
-                    self.state = GameState::Playing;
-                    enemy_sprites = self.enemies.iter().map(|_| Sprite::from_texture(enemy_texture.to_owned())).collect::<Vec<_>>();
-                },
-                GameState::PlayerDied => {
-                    self.reset_screen();
-                    self.state = GameState::Playing;
-                },
-                GameState::Playing => {
-                    self.player = self.player.update(self);
-                    if let Some(Button::Keyboard(key)) = e.press_args() {
-                        self.player.button_pressed(&key);
-                    };
-                    if let Some(Button::Keyboard(key)) = e.release_args() {
-                        self.player.button_released(&key);
-                    };
-                    self.update();
-        
-                    if self.player.is_dead() {
-                        self.state = GameState::PlayerDied;
-                    } else {
-                        window.draw_2d(&e, |c, g, _| {
-                            clear(color::GRAY, g);
-                            for (i, l) in self.lines.iter().enumerate() {
-                                let mut color = color::RED;
-                                if (i == (self.lines.len() - 1)) && (l.len() >= WARN_LINE_LEN) {
-                                    color = color::YELLOW;
-                                }
-                                piston_window::line_from_to(
-                                    color,
-                                    3.0,
-                                    [l.from.x as f64, l.from.y as f64],
-                                    [l.to.x as f64, l.to.y as f64],
-                                    c.transform,
-                                    g
-                                );
-                            }
-            
-                            let mut enemies_to_remove: Vec<usize> = vec![];
-                            for (i, e) in enemy_sprites.iter_mut().enumerate() {
-                                if let Some(enemy) = self.enemies.get_mut(i) {
-                                    e.set_position(enemy.get_position().x as f64, enemy.get_position().y as f64);
-                                    match enemy.get_state() {
-                                        EnemyState::Alive => {e.set_src_rect([0.0, 0.0, SPRITE_WIDTH as f64, SPRITE_WIDTH as f64])},
-                                        EnemyState::Dead => {
-                                            let (state, ai) = enemy.update_dead_animation();
-                                            match state {
-                                                EnemyState::Dead => {
-                                                    e.set_src_rect([SPRITE_WIDTH as f64 * (ai as f64 + 1.0), 0.0, SPRITE_WIDTH as f64, SPRITE_WIDTH as f64]);
-                                                },
-                                                _ => {enemies_to_remove.push(i);}
-                                            }
-                                        },
-                                        EnemyState::Done => {enemies_to_remove.push(i)},
-                                    }
-                                    if enemy.get_state() != EnemyState::Done {
-                                        e.draw(c.transform, g);
-                                    }
-                                }
-                            }
-            
-                            if enemies_to_remove.len() > 0 {
-                                for i in enemies_to_remove.iter().rev() {
-                                    enemy_sprites.remove(*i);
-                                    self.enemies.remove(*i);
-                                }
-                            }
-            
-                            player_sprite.set_position(self.player.get_cur_position().x as f64, self.player.get_cur_position().y as f64);
-                            match self.player.get_cur_direction() {
-                                Direction::Down => {player_sprite.set_rotation(90.0)},
-                                Direction::Left => {player_sprite.set_rotation(180.0)},
-                                Direction::Right => {player_sprite.set_rotation(0.0)},
-                                Direction::Up => {player_sprite.set_rotation(270.0)},
-                                _ => {}
-                            }
-                            player_sprite.draw(c.transform, g);
-                        });
-    
-                        if enemy_sprites.len() <= 0 {
-                            self.enemies.clear();
-                            self.state = GameState::LevelComplete;
-                        }    
+            #[cfg(feature = "gamepad")]
+            if let Some(gamepads) = gamepads.as_mut() {
+                for event in gamepads.poll() {
+                    self.gamepad_event(event);
+                }
+            }
+
+            for name in self.assets.poll_changes() {
+                println!("reloading {}", name);
+                match name.as_str() {
+                    "hero.png" => match create_texture("hero.png", self.load_image("hero.png")) {
+                        Ok(texture) => hero_texture = texture,
+                        Err(e) => println!("{}", e),
+                    },
+                    "enemy.png" => match create_texture("enemy.png", self.load_image("enemy.png")) {
+                        Ok(texture) => enemy_texture = texture,
+                        Err(e) => println!("{}", e),
+                    },
+                    "hero.json" | "enemy.json" => self.reload_sheets(),
+                    "enemies.json" => self.enemy_defs = Self::load_enemy_defs(&mut self.assets),
+                    _ => {},
+                }
+            }
+
+            match e.press_args() {
+                Some(Button::Keyboard(key)) => {
+                    if let Some(Action::Fullscreen) = self.press_key(key) {
+                        self.fullscreen = !self.fullscreen;
+                        let w = window.window.ctx.window();
+                        w.set_fullscreen(self.fullscreen.then(|| Fullscreen::Borderless(w.current_monitor())));
                     }
                 },
+                Some(Button::Mouse(MouseButton::Left)) => {
+                    dragging = true;
+                    self.pointer(self.cursor, true, false);
+                },
+                Some(Button::Mouse(MouseButton::Right)) => self.clear_waypoints(0),
+                _ => {},
+            }
+            if let Some(text) = e.text_args() {
+                self.type_text(&text);
+            }
+            match e.release_args() {
+                Some(Button::Keyboard(key)) => self.release_key(key),
+                Some(Button::Mouse(MouseButton::Left)) => dragging = false,
+                _ => {},
+            }
+            if let Some(p) = e.mouse_cursor_args() {
+                let p = Viewport::new(self.arena_size, window.size()).arena_position(p);
+                self.pointer(p, false, dragging);
+            }
+            // The game waits behind the title and game over screens rather than running on.
+            let now = Instant::now();
+            if self.screen.is_none() {
+                self.step(now - last_step);
+            }
+            self.update_spectators();
+            last_step = now;
+
+            if let GameState::Playing = self.state {
+                window.draw_2d(&e, |c, g, _| {
+                    let viewport = Viewport::new(self.arena_size, Size::from(c.get_view_size()));
+                    self.draw(&mut PistonRenderer::new(c, viewport, g, hero_texture.clone(), enemy_texture.clone()));
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Players start side by side in the top left corner, two to a row.
+fn start_position(index: usize) -> Position {
+    Position {
+        x: START_X + (index % 2) as i32 * SPRITE_WIDTH,
+        y: START_Y + (index / 2) as i32 * SPRITE_WIDTH,
+    }
+}
+
+// The rectangle around `points`, corners listed clockwise from the top left.
+fn bounding_box(points: &[Position]) -> Vec<Position> {
+    let start = Position {
+        x: points.iter().map(|p| p.x).min().unwrap_or_default(),
+        y: points.iter().map(|p| p.y).min().unwrap_or_default(),
+    };
+    let end = Position {
+        x: points.iter().map(|p| p.x).max().unwrap_or_default(),
+        y: points.iter().map(|p| p.y).max().unwrap_or_default(),
+    };
+    vec![start, Position { x: end.x, y: start.y }, end, Position { x: start.x, y: end.y }]
+}
+
+fn lerp(from: [f64; 2], to: [f64; 2], t: f64) -> [f64; 2] {
+    [from[0] + (to[0] - from[0]) * t, from[1] + (to[1] - from[1]) * t]
+}
+
+fn blend(from: [f32; 4], to: [f32; 4], t: f64) -> [f32; 4] {
+    let t = t as f32;
+    [
+        from[0] + (to[0] - from[0]) * t,
+        from[1] + (to[1] - from[1]) * t,
+        from[2] + (to[2] - from[2]) * t,
+        from[3] + (to[3] - from[3]) * t
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game() -> Boxes {
+        set_debug(false);
+        let mut boxes = Boxes::new(800, 800, 1, 10);
+        boxes.set_seed(1);
+        boxes
+    }
+
+    fn run(boxes: &mut Boxes, ticks: u32) {
+        for _ in 0..ticks {
+            boxes.step(SCREENSHOT_TICK);
+        }
+    }
+
+    fn position(boxes: &Boxes) -> Position {
+        boxes.players[0].player.get_cur_position()
+    }
+
+    #[test]
+    fn dpad_moves_player_one() {
+        let mut boxes = game();
+        let start = position(&boxes);
+        boxes.gamepad_event(GamepadEvent::Pressed(GamepadButton::DPadRight));
+        run(&mut boxes, 30);
+        boxes.gamepad_event(GamepadEvent::Released(GamepadButton::DPadRight));
+        let moved = position(&boxes);
+        assert!(moved.x > start.x && moved.y == start.y);
+    }
+
+    #[test]
+    fn stick_moves_outside_dead_zone() {
+        let mut boxes = game();
+        let start = position(&boxes);
+        boxes.gamepad_event(GamepadEvent::Stick { x: 0.1, y: 0.2 });
+        run(&mut boxes, 30);
+        assert_eq!(position(&boxes), start);
+
+        boxes.gamepad_event(GamepadEvent::Stick { x: 0.2, y: 0.9 });
+        run(&mut boxes, 30);
+        let moved = position(&boxes);
+        assert!(moved.y > start.y && moved.x == start.x);
+    }
+
+    #[test]
+    fn analog_stick_steers() {
+        let mut boxes = game();
+        boxes.set_movement(Movement::Analog);
+        let start = position(&boxes);
+        boxes.gamepad_event(GamepadEvent::Stick { x: 0.7, y: 0.7 });
+        run(&mut boxes, 30);
+        let moved = position(&boxes);
+        assert!(moved.x > start.x && moved.y > start.y);
+    }
+
+    #[test]
+    fn start_pauses_and_south_resumes() {
+        let mut boxes = game();
+        boxes.gamepad_event(GamepadEvent::Pressed(GamepadButton::Start));
+        boxes.gamepad_event(GamepadEvent::Released(GamepadButton::Start));
+        assert!(boxes.paused);
+
+        let start = position(&boxes);
+        boxes.gamepad_event(GamepadEvent::Pressed(GamepadButton::DPadRight));
+        run(&mut boxes, 30);
+        assert_eq!(position(&boxes), start);
+
+        boxes.gamepad_event(GamepadEvent::Pressed(GamepadButton::South));
+        assert!(!boxes.paused);
+    }
+
+    // Holds `actions` for `player` until they reach `done`.
+    fn hold(boxes: &mut Boxes, player: usize, actions: &[Action], done: impl Fn(Position) -> bool) {
+        let at = |boxes: &Boxes| boxes.players[player].player.get_cur_position();
+        for action in actions {
+            boxes.press_action(player, *action);
+        }
+        for _ in 0..2000 {
+            if done(at(boxes)) {
+                break;
             }
+            run(boxes, 1);
+        }
+        for action in actions {
+            boxes.release_action(player, *action);
         }
+        assert!(done(at(boxes)));
+    }
+
+    // Holds `action` until the player reaches `done`.
+    fn walk(boxes: &mut Boxes, action: Action, done: impl Fn(Position) -> bool) {
+        hold(boxes, 0, &[action], done);
+    }
+
+    // An open arena with `count` enemies parked out of the way.
+    fn clear_arena(boxes: &mut Boxes, count: usize) {
+        boxes.level.obstacles.clear();
+        boxes.enemies.resize(count, boxes.enemies[0].clone());
+        for (i, e) in boxes.enemies.iter_mut().enumerate() {
+            e.set_position(Position { x: 700, y: 700 - i as i32 * 50 });
+        }
+    }
+
+    fn trail_len(slot: &PlayerSlot) -> f64 {
+        slot.lines.iter().map(|l| l.len()).sum()
+    }
+
+    fn alive(boxes: &Boxes) -> Vec<bool> {
+        boxes.enemies.iter().map(|e| e.is_alive()).collect()
+    }
+
+    #[test]
+    fn cardinal_loop_captures_its_box() {
+        let mut boxes = game();
+        clear_arena(&mut boxes, 2);
+        walk(&mut boxes, Action::MoveDown, |p| p.y >= 100);
+        walk(&mut boxes, Action::MoveRight, |p| p.x >= 200);
+        walk(&mut boxes, Action::MoveDown, |p| p.y >= 200);
+        walk(&mut boxes, Action::MoveLeft, |p| p.x <= 100);
+        walk(&mut boxes, Action::MoveUp, |p| p.y <= 115);
+        boxes.enemies[0].set_position(Position { x: 150, y: 150 });
+        boxes.enemies[1].set_position(Position { x: 300, y: 150 });
+        walk(&mut boxes, Action::MoveUp, |p| p.y <= 90);
+
+        assert_eq!(alive(&boxes), [false, true]);
+        assert_eq!(boxes.players[0].caught, 1);
+        assert_eq!(boxes.captures.len(), 1);
+        assert!(boxes.players[0].lines.len() <= 1);
+    }
+
+    // The enemy in the corner of the box the loop spans is outside the triangle it drew.
+    #[test]
+    fn angled_loop_captures_only_inside_it() {
+        let mut boxes = game();
+        boxes.set_movement(Movement::EightWay);
+        clear_arena(&mut boxes, 2);
+        walk(&mut boxes, Action::MoveDown, |p| p.y >= 100);
+        walk(&mut boxes, Action::MoveRight, |p| p.x >= 200);
+        walk(&mut boxes, Action::MoveDown, |p| p.y >= 200);
+        hold(&mut boxes, 0, &[Action::MoveUp, Action::MoveLeft], |p| p.y <= 112);
+        boxes.enemies[0].set_position(Position { x: 175, y: 115 });
+        boxes.enemies[1].set_position(Position { x: 120, y: 180 });
+        hold(&mut boxes, 0, &[Action::MoveUp, Action::MoveLeft], |p| p.y <= 95);
+
+        assert_eq!(alive(&boxes), [false, true]);
+        assert_eq!(boxes.players[0].caught, 1);
+        assert_eq!(boxes.captures.len(), 1);
+    }
+
+    // Player two lays a wall that player one crosses on the way out and again on the way
+    // back, which only closes a loop if their trails can be combined.
+    fn cross_twice(shared: bool) -> Boxes {
+        let mut boxes = game();
+        boxes.set_players(2);
+        boxes.set_shared_loops(shared);
+        clear_arena(&mut boxes, 1);
+        let wall = boxes.players[1].player.get_cur_position().x;
+        hold(&mut boxes, 1, &[Action::MoveDown], |p| p.y >= 250);
+        walk(&mut boxes, Action::MoveDown, |p| p.y >= 100);
+        walk(&mut boxes, Action::MoveRight, |p| p.x >= wall + 100);
+        walk(&mut boxes, Action::MoveDown, |p| p.y >= 200);
+        walk(&mut boxes, Action::MoveLeft, |p| p.x <= wall + 20);
+        boxes.enemies[0].set_position(Position { x: wall + 60, y: 150 });
+        walk(&mut boxes, Action::MoveLeft, |p| p.x < wall);
+        boxes
+    }
+
+    #[test]
+    fn shared_loop_is_credited_to_whoever_closed_it() {
+        let boxes = cross_twice(true);
+        assert_eq!(alive(&boxes), [false]);
+        assert_eq!((boxes.players[0].caught, boxes.players[1].caught), (1, 0));
+        assert_eq!(boxes.captures.len(), 1);
+        assert!(boxes.players.iter().all(|s| trail_len(s) < 50.0));
+    }
+
+    #[test]
+    fn crossing_a_trail_twice_needs_shared_loops() {
+        let boxes = cross_twice(false);
+        assert_eq!(alive(&boxes), [true]);
+        assert!(boxes.captures.is_empty());
+        assert!(trail_len(&boxes.players[1]) > 200.0);
+    }
+
+    #[test]
+    fn crossing_an_opponents_trail_cuts_it() {
+        let mut boxes = game();
+        boxes.set_mode(GameMode::Versus);
+        clear_arena(&mut boxes, 1);
+        let wall = boxes.players[1].player.get_cur_position().x;
+        hold(&mut boxes, 1, &[Action::MoveDown], |p| p.y >= 250);
+        hold(&mut boxes, 1, &[Action::MoveRight], |p| p.x >= 200);
+        let before = trail_len(&boxes.players[1]);
+        walk(&mut boxes, Action::MoveDown, |p| p.y >= 100);
+        walk(&mut boxes, Action::MoveRight, |p| p.x >= wall + 50);
+
+        // Only the part between the cut and where they are now is left.
+        let cut = boxes.players[1].lines[0].from;
+        assert_eq!(cut.x, wall);
+        assert!((99..=102).contains(&cut.y), "cut at {:?}", cut);
+        assert!((before - trail_len(&boxes.players[1]) - (cut.y - start_position(1).y) as f64).abs() <= 2.0);
+        assert_eq!(boxes.players[1].lines.last().map(|l| l.to), Some(boxes.players[1].player.get_cur_position()));
+    }
+
+    #[test]
+    fn trapped_opponent_is_knocked_out_but_keeps_playing() {
+        let mut boxes = game();
+        boxes.set_mode(GameMode::Versus);
+        clear_arena(&mut boxes, 1);
+        hold(&mut boxes, 1, &[Action::MoveRight], |p| p.x >= 200);
+        hold(&mut boxes, 1, &[Action::MoveDown], |p| p.y >= 300);
+        walk(&mut boxes, Action::MoveDown, |p| p.y >= 250);
+        walk(&mut boxes, Action::MoveRight, |p| p.x >= 250);
+        walk(&mut boxes, Action::MoveDown, |p| p.y >= 350);
+        walk(&mut boxes, Action::MoveLeft, |p| p.x <= 150);
+        walk(&mut boxes, Action::MoveUp, |p| p.y <= 240);
+
+        let slot = &boxes.players[1];
+        assert_eq!(slot.lives, LIVES - 1);
+        assert!(slot.is_alive() && !slot.player.is_dead());
+        assert!(!slot.stunned.is_zero());
+        assert_eq!(slot.player.get_cur_position(), start_position(1));
+        assert!(trail_len(slot) < 1.0);
+        assert_eq!(boxes.players[0].lives, LIVES);
+        assert_eq!(boxes.captures.len(), 1);
+    }
+
+    #[test]
+    fn territory_trail_outlasts_reset_length() {
+        let mut boxes = game();
+        boxes.set_mode(GameMode::Territory(75.0));
+        boxes.level.obstacles.clear();
+        boxes.enemies.truncate(1);
+        boxes.enemies[0].set_position(Position { x: 700, y: 700 });
+
+        walk(&mut boxes, Action::MoveRight, |p| p.x >= 100);
+        walk(&mut boxes, Action::MoveDown, |p| p.y >= 500);
+        assert!(boxes.players[0].lines.iter().map(|l| l.len()).sum::<f64>() > MAX_LINE_LEN);
+        walk(&mut boxes, Action::MoveLeft, |p| p.x <= 16);
+
+        // The strip between the trail and the left edge, not the rest of the arena.
+        assert!(boxes.territory.is_claimed(Position { x: 50, y: 300 }));
+        assert!(boxes.territory.is_claimed(Position { x: 50, y: 480 }));
+        assert!(!boxes.territory.is_claimed(Position { x: 400, y: 400 }));
+        assert!(!boxes.territory.is_claimed(Position { x: 50, y: 600 }));
+    }
+
+    #[test]
+    fn malformed_bindings_fall_back_to_defaults() {
+        let path = std::env::temp_dir().join(format!("boxes-bindings-fallback-{}.json", std::process::id()));
+        std::fs::write(&path, "{\"preset\": \"wasd\", \"keys\": [").unwrap();
+        let mut boxes = game();
+        boxes.set_preset(Preset::Vim);
+        boxes.set_bindings_path(path.clone());
+        assert_eq!(boxes.players[0].bindings.get_preset(), Preset::Arrows);
+        assert_eq!(boxes.players[0].bindings.action(Key::Up), Some(Action::MoveUp));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn sheets_missing_animations_fall_back() {
+        let dir = std::env::temp_dir().join(format!("boxes-sheets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("enemy.json"), r#"{
+            "frame_width": 16,
+            "frame_height": 16,
+            "animations": { "idle": { "frames": [0], "durations": [100], "looping": true } }
+        }"#).unwrap();
+
+        let mut boxes = game();
+        boxes.set_asset_root(dir.clone());
+        assert_eq!(boxes.enemy_sheet.frame_width, SPRITE_WIDTH as u32);
+        assert!(boxes.enemy_sheet.missing(&["idle", "death"]).is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn only_simulation_input_is_trusted_from_clients() {
+        for player in 0..2 {
+            for action in [Action::MoveUp, Action::MoveDown, Action::MoveLeft, Action::MoveRight] {
+                assert!(Boxes::is_client_input(player, InputEvent::Pressed(action)));
+                assert!(Boxes::is_client_input(player, InputEvent::Released(action)));
+            }
+            for action in [Action::QuickSave, Action::QuickLoad, Action::Rebind, Action::Debug, Action::Fullscreen, Action::Confirm] {
+                assert!(!Boxes::is_client_input(player, InputEvent::Pressed(action)));
+                assert!(!Boxes::is_client_input(player, InputEvent::Released(action)));
+            }
+            assert!(Boxes::is_client_input(player, InputEvent::Waypoint { x: 10, y: 10 }));
+            assert!(Boxes::is_client_input(player, InputEvent::ClearWaypoints));
+            assert!(Boxes::is_client_input(player, InputEvent::Steer { heading: Some(90.0) }));
+        }
+    }
+
+    #[test]
+    fn only_the_host_pauses_a_served_game() {
+        assert!(Boxes::is_client_input(0, InputEvent::Pressed(Action::Pause)));
+        assert!(Boxes::is_client_input(0, InputEvent::Released(Action::Pause)));
+        assert!(!Boxes::is_client_input(1, InputEvent::Pressed(Action::Pause)));
+        assert!(!Boxes::is_client_input(1, InputEvent::Released(Action::Pause)));
     }
 }
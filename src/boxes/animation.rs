@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+use serde::{
+    Deserialize,
+    Serialize
+};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Animation {
+    pub frames: Vec<u32>,
+    pub durations: Vec<u64>,
+    #[serde(default)]
+    pub looping: bool,
+}
+
+impl Animation {
+    fn duration(&self, frame: usize) -> Duration {
+        let ms = self.durations.get(frame).or(self.durations.last()).copied().unwrap_or(0);
+        Duration::from_millis(ms)
+    }
+}
+
+// Describes how the frames of a sprite sheet PNG are laid out. Frames are numbered left to
+// right, top to bottom; without `columns` the sheet is treated as a single horizontal strip.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct SpriteSheet {
+    pub frame_width: u32,
+    pub frame_height: u32,
+    #[serde(default)]
+    pub columns: Option<u32>,
+    pub animations: HashMap<String, Animation>,
+}
+
+impl SpriteSheet {
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    // The first of `required` the sheet has no animation for.
+    pub fn missing<'a>(&self, required: &[&'a str]) -> Option<&'a str> {
+        required.iter().find(|name| !self.animations.contains_key(**name)).copied()
+    }
+
+    pub fn src_rect(&self, frame: u32) -> [f64; 4] {
+        let (col, row) = match self.columns {
+            Some(columns) if columns > 0 => (frame % columns, frame / columns),
+            _ => (frame, 0),
+        };
+        [
+            (col * self.frame_width) as f64,
+            (row * self.frame_height) as f64,
+            self.frame_width as f64,
+            self.frame_height as f64
+        ]
+    }
+}
+
+// Timing is driven by the game clock through `update`, so the same ticks always produce the
+// same frames. Saves leave the sheet out, and it is put back once they are loaded.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Animator {
+    #[serde(skip)]
+    sheet: Rc<SpriteSheet>,
+    animation: String,
+    frame: usize,
+    frame_elapsed: Duration,
+    finished: bool,
+}
+
+impl Animator {
+    pub fn new(sheet: Rc<SpriteSheet>, animation: &str) -> Self {
+        Self {
+            sheet,
+            animation: animation.to_string(),
+            frame: 0,
+            frame_elapsed: Duration::ZERO,
+            finished: false,
+        }
+    }
+
+    // Switching to the animation that is already playing keeps its current frame.
+    pub fn play(&mut self, animation: &str) {
+        if self.animation == animation {
+            return;
+        }
+
+        self.restart(animation);
+    }
+
+    pub fn restart(&mut self, animation: &str) {
+        self.animation = animation.to_string();
+        self.frame = 0;
+        self.frame_elapsed = Duration::ZERO;
+        self.finished = false;
+    }
+
+    pub fn set_sheet(&mut self, sheet: Rc<SpriteSheet>) {
+        self.sheet = sheet;
+    }
+
+    // An animation the sheet doesn't have counts as finished straight away, so nothing
+    // waits forever on one. Time left over from a frame carries into the next.
+    pub fn update(&mut self, dt: Duration) {
+        let animation = match self.sheet.animations.get(&self.animation) {
+            Some(a) => a,
+            None => {
+                self.finished = true;
+                return;
+            },
+        };
+        if self.finished {
+            return;
+        }
+        self.frame_elapsed += dt;
+        while !self.finished {
+            let duration = animation.duration(self.frame);
+            if self.frame_elapsed <= duration {
+                break;
+            }
+
+            self.frame_elapsed -= duration;
+            if self.frame + 1 < animation.frames.len() {
+                self.frame += 1;
+            } else if animation.looping {
+                self.frame = 0;
+            } else {
+                self.finished = true;
+            }
+            // Frames that last no time at all are still shown for an update each.
+            if duration.is_zero() {
+                self.frame_elapsed = Duration::ZERO;
+                break;
+            }
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn src_rect(&self) -> [f64; 4] {
+        let frame = self.sheet.animations.get(&self.animation)
+            .and_then(|a| a.frames.get(self.frame))
+            .copied()
+            .unwrap_or(0);
+        self.sheet.src_rect(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sheet() -> Rc<SpriteSheet> {
+        Rc::new(SpriteSheet::from_json(r#"{
+            "frame_width": 32,
+            "frame_height": 32,
+            "animations": {
+                "walk": { "frames": [0, 1, 2], "durations": [100], "looping": true },
+                "death": { "frames": [3, 4], "durations": [50, 100] }
+            }
+        }"#).unwrap())
+    }
+
+    fn frame(animator: &Animator) -> u32 {
+        animator.src_rect()[0] as u32 / 32
+    }
+
+    #[test]
+    fn looping_wraps_around() {
+        let mut animator = Animator::new(sheet(), "walk");
+        for expected in [0, 1, 2, 0, 1] {
+            assert_eq!(frame(&animator), expected);
+            animator.update(Duration::from_millis(101));
+        }
+        assert!(!animator.is_finished());
+    }
+
+    #[test]
+    fn leftover_time_carries_over() {
+        let mut animator = Animator::new(sheet(), "walk");
+        // Eleven ticks of 30ms run just past three frames of 100ms, back to the first.
+        for _ in 0..11 {
+            animator.update(Duration::from_millis(30));
+        }
+        assert_eq!(frame(&animator), 0);
+        animator.update(Duration::from_millis(250));
+        assert_eq!(frame(&animator), 2);
+    }
+
+    #[test]
+    fn one_shot_finishes_on_last_frame() {
+        let mut animator = Animator::new(sheet(), "death");
+        animator.update(Duration::from_millis(60));
+        assert_eq!(frame(&animator), 4);
+        assert!(!animator.is_finished());
+        animator.update(Duration::from_millis(100));
+        assert!(animator.is_finished());
+        assert_eq!(frame(&animator), 4);
+
+        animator.restart("death");
+        assert!(!animator.is_finished());
+        assert_eq!(frame(&animator), 3);
+    }
+
+    #[test]
+    fn missing_animation_is_finished() {
+        let mut animator = Animator::new(sheet(), "idle");
+        animator.update(Duration::from_millis(1));
+        assert!(animator.is_finished());
+    }
+}
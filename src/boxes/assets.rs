@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::Deserialize;
+use zip::ZipArchive;
+use zip::result::ZipError;
+
+const DEFAULT_ROOT: &str = "assets";
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const MANIFEST: &str = "manifest.json";
+
+const EMBEDDED: [(&str, &[u8]); 5] = [
+    ("hero.png", include_bytes!("../../images/hero.png")),
+    ("hero.json", include_bytes!("../../images/hero.json")),
+    ("enemy.png", include_bytes!("../../images/enemy.png")),
+    ("enemy.json", include_bytes!("../../images/enemy.json")),
+    ("enemies.json", include_bytes!("../../images/enemies.json")),
+];
+
+pub fn embedded(name: &str) -> Option<&'static [u8]> {
+    EMBEDDED.iter().find(|(n, _)| *n == name).map(|(_, bytes)| *bytes)
+}
+
+#[derive(Debug)]
+pub enum PackError {
+    Io(io::Error),
+    Zip(ZipError),
+    Manifest(serde_json::Error),
+}
+
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PackError::Io(e) => write!(f, "{}", e),
+            PackError::Zip(e) => write!(f, "invalid archive: {}", e),
+            PackError::Manifest(e) => write!(f, "invalid {}: {}", MANIFEST, e),
+        }
+    }
+}
+
+impl From<io::Error> for PackError {
+    fn from(e: io::Error) -> Self {
+        PackError::Io(e)
+    }
+}
+
+impl From<ZipError> for PackError {
+    fn from(e: ZipError) -> Self {
+        PackError::Zip(e)
+    }
+}
+
+impl From<serde_json::Error> for PackError {
+    fn from(e: serde_json::Error) -> Self {
+        PackError::Manifest(e)
+    }
+}
+
+// `overrides` maps an asset name to the path inside the archive that replaces it, so a
+// theme can keep its files in its own folder.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PackManifest {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub overrides: HashMap<String, String>,
+}
+
+struct AssetPack {
+    manifest: PackManifest,
+    archive: ZipArchive<File>,
+}
+
+impl AssetPack {
+    fn open(path: &Path) -> Result<Self, PackError> {
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+        let mut json = String::new();
+        archive.by_name(MANIFEST)?.read_to_string(&mut json)?;
+        Ok(Self {
+            manifest: serde_json::from_str(&json)?,
+            archive,
+        })
+    }
+
+    fn read(&mut self, name: &str) -> Option<Vec<u8>> {
+        let path = self.manifest.overrides.get(name).cloned().unwrap_or_else(|| name.to_string());
+        let mut file = self.archive.by_name(&path).ok()?;
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes).ok()?;
+        Some(bytes)
+    }
+}
+
+// Loads assets from mounted packs, most recently mounted first, then from a directory on
+// disk, falling back to the copies built into the binary. Files that were read from disk
+// are polled for changes so they can be reloaded in place.
+pub struct AssetManager {
+    root: PathBuf,
+    packs: Vec<AssetPack>,
+    modified: HashMap<String, Option<SystemTime>>,
+    last_poll: Instant,
+}
+
+impl AssetManager {
+    pub fn new() -> Self {
+        let root = std::env::var_os("BOXES_ASSETS")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_ROOT));
+        Self {
+            root,
+            packs: vec![],
+            modified: HashMap::new(),
+            last_poll: Instant::now(),
+        }
+    }
+
+    pub fn set_root(&mut self, root: PathBuf) {
+        self.root = root;
+        self.modified.clear();
+    }
+
+    fn modified_time(&self, name: &str) -> Option<SystemTime> {
+        fs::metadata(self.root.join(name)).and_then(|m| m.modified()).ok()
+    }
+
+    pub fn mount_pack(&mut self, path: &Path) -> Result<PackManifest, PackError> {
+        let pack = AssetPack::open(path)?;
+        let manifest = pack.manifest.clone();
+        self.packs.push(pack);
+        Ok(manifest)
+    }
+
+    pub fn load(&mut self, name: &str) -> Option<Vec<u8>> {
+        if let Some(bytes) = self.packs.iter_mut().rev().find_map(|p| p.read(name)) {
+            return Some(bytes);
+        }
+
+        self.modified.insert(name.to_string(), self.modified_time(name));
+        match fs::read(self.root.join(name)) {
+            Ok(bytes) => Some(bytes),
+            Err(_) => embedded(name).map(|b| b.to_vec()),
+        }
+    }
+
+    // Returns the names of previously loaded assets whose file on disk has appeared,
+    // disappeared or been modified since the last poll.
+    pub fn poll_changes(&mut self) -> Vec<String> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return vec![];
+        }
+        self.last_poll = Instant::now();
+
+        let mut changed = vec![];
+        let names = self.modified.keys().cloned().collect::<Vec<_>>();
+        for name in names {
+            let modified = self.modified_time(&name);
+            if self.modified.get(&name).copied().flatten() != modified {
+                self.modified.insert(name.clone(), modified);
+                changed.push(name);
+            }
+        }
+
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::ZipWriter;
+    use zip::write::FileOptions;
+
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("boxes-assets-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn manager(root: &Path) -> AssetManager {
+        let mut assets = AssetManager::new();
+        assets.set_root(root.to_path_buf());
+        assets
+    }
+
+    fn pack(path: &Path, manifest: &str, files: &[(&str, &str)]) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        zip.start_file(MANIFEST, FileOptions::default()).unwrap();
+        zip.write_all(manifest.as_bytes()).unwrap();
+        for (name, contents) in files {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn pack_manifest_and_overrides() {
+        let dir = scratch("manifest");
+        let path = dir.join("theme.zip");
+        pack(&path, r#"{
+            "name": "night",
+            "version": "1.2",
+            "overrides": { "enemies.json": "night/enemies.json" }
+        }"#, &[("night/enemies.json", "night enemies"), ("hero.json", "night hero")]);
+
+        let mut assets = manager(&dir);
+        let manifest = assets.mount_pack(&path).unwrap();
+        assert_eq!((manifest.name.as_str(), manifest.version.as_str()), ("night", "1.2"));
+        assert_eq!(manifest.overrides["enemies.json"], "night/enemies.json");
+        assert_eq!(assets.load("enemies.json").unwrap(), b"night enemies");
+        assert_eq!(assets.load("hero.json").unwrap(), b"night hero");
+        // Anything the pack leaves out still comes from the built in copies.
+        assert_eq!(assets.load("hero.png").unwrap(), embedded("hero.png").unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn pack_without_manifest_is_rejected() {
+        let dir = scratch("no-manifest");
+        let path = dir.join("theme.zip");
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        zip.start_file("hero.json", FileOptions::default()).unwrap();
+        zip.finish().unwrap();
+
+        let mut assets = manager(&dir);
+        assert!(matches!(assets.mount_pack(&path), Err(PackError::Zip(_))));
+        assert!(matches!(assets.mount_pack(&dir.join("missing.zip")), Err(PackError::Io(_))));
+        fs::write(&path, b"not a zip").unwrap();
+        assert!(matches!(assets.mount_pack(&path), Err(PackError::Zip(_))));
+        assert_eq!(assets.load("hero.json").unwrap(), embedded("hero.json").unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn newest_pack_wins() {
+        let dir = scratch("order");
+        fs::write(dir.join("enemies.json"), "directory enemies").unwrap();
+        fs::write(dir.join("hero.json"), "directory hero").unwrap();
+        let manifest = r#"{ "name": "theme", "version": "1" }"#;
+        pack(&dir.join("old.zip"), manifest, &[("enemies.json", "old enemies"), ("enemy.json", "old enemy")]);
+        pack(&dir.join("new.zip"), manifest, &[("enemies.json", "new enemies")]);
+
+        let mut assets = manager(&dir);
+        assets.mount_pack(&dir.join("old.zip")).unwrap();
+        assets.mount_pack(&dir.join("new.zip")).unwrap();
+        assert_eq!(assets.load("enemies.json").unwrap(), b"new enemies");
+        assert_eq!(assets.load("enemy.json").unwrap(), b"old enemy");
+        assert_eq!(assets.load("hero.json").unwrap(), b"directory hero");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn changed_files_are_reported() {
+        let dir = scratch("reload");
+        let path = dir.join("enemies.json");
+        fs::write(&path, "first").unwrap();
+
+        let mut assets = manager(&dir);
+        assets.load("enemies.json");
+        assets.load("hero.json");
+        assets.last_poll -= POLL_INTERVAL;
+        assert!(assets.poll_changes().is_empty());
+
+        let modified = fs::metadata(&path).unwrap().modified().unwrap() + Duration::from_secs(10);
+        File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        // Polls closer together than the interval don't look at the files.
+        assert!(assets.poll_changes().is_empty());
+        assets.last_poll -= POLL_INTERVAL;
+        assert_eq!(assets.poll_changes(), vec!["enemies.json".to_string()]);
+
+        fs::write(dir.join("hero.json"), "appeared").unwrap();
+        fs::remove_file(&path).unwrap();
+        assets.last_poll -= POLL_INTERVAL;
+        let mut changed = assets.poll_changes();
+        changed.sort();
+        assert_eq!(changed, vec!["enemies.json".to_string(), "hero.json".to_string()]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,42 +1,68 @@
-use std::time::{Instant, Duration};
+use std::rc::Rc;
+use std::time::Duration;
 
 use piston::{
     Position,
     Size
 };
+use serde::{
+    Deserialize,
+    Serialize
+};
 
+use super::animation::{
+    Animator,
+    SpriteSheet
+};
+use super::level::Obstacle;
 use super::player::Player;
+use super::save::PositionDef;
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum EnemyState {
     Alive,
     Dead,
     Done
 }
 
+// `move_interval` is the number of milliseconds between steps.
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct EnemyDef {
+    pub aggressive: bool,
+    pub move_rate: i32,
+    pub move_interval: u64,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Enemy {
     aggressive: bool,
+    move_rate: i32,
+    move_interval: Duration,
+    #[serde(with = "PositionDef")]
     position: Position,
     height: i32,
     width: i32,
     state: EnemyState,
-    animation_index: i32,
-    animation_start: Instant
+    animator: Animator,
+    move_elapsed: Duration
 }
 
 impl Enemy {
-    pub fn new(x: i32, y: i32, height: i32, width: i32, aggressive: bool) -> Self {
+    pub fn new(x: i32, y: i32, height: i32, width: i32, def: &EnemyDef, sheet: Rc<SpriteSheet>) -> Self {
         Self {
-            aggressive: aggressive,
+            aggressive: def.aggressive,
+            move_rate: def.move_rate,
+            move_interval: Duration::from_millis(def.move_interval),
             position: Position {
-                x: x,
-                y: y,
+                x,
+                y,
             },
-            height: height,
-            width: width,
+            height,
+            width,
             state: EnemyState::Alive,
-            animation_index: 0,
-            animation_start: Instant::now(),
+            animator: Animator::new(sheet, "idle"),
+            move_elapsed: Duration::ZERO,
         }
     }
 
@@ -45,13 +71,18 @@ impl Enemy {
     }
 
     pub fn is_alive(&self) -> bool {
-        return self.state == EnemyState::Alive;
+        self.state == EnemyState::Alive
     }
 
     pub fn get_position(&self) -> Position {
         self.position
     }
 
+    #[cfg(test)]
+    pub fn set_position(&mut self, position: Position) {
+        self.position = position;
+    }
+
     pub fn get_height(&self) -> i32 {
         self.height
     }
@@ -62,25 +93,30 @@ impl Enemy {
 
     pub fn dead(& mut self) {
         self.state = EnemyState::Dead;
-        self.animation_index = 0;
-        self.animation_start = Instant::now();
+        self.animator.restart("death");
     }
 
     pub fn get_state(&self) -> EnemyState {
         self.state
     }
 
-    pub fn update_dead_animation(& mut self) -> (EnemyState, i32) {
-        if self.animation_start.elapsed() > Duration::from_secs(1) {
-            if self.animation_index >= 3 {
-                self.state = EnemyState::Done;
-            } else {
-                self.animation_start = Instant::now();
-                self.animation_index += 1;
-            }
+    // Advances the move timer and animation by `dt` of game time.
+    pub fn update(& mut self, dt: Duration) -> EnemyState {
+        self.move_elapsed += dt;
+        self.animator.update(dt);
+        if self.state == EnemyState::Dead && self.animator.is_finished() {
+            self.state = EnemyState::Done;
         }
- 
-        (self.state, self.animation_index)
+
+        self.state
+    }
+
+    pub fn set_sprite_sheet(&mut self, sheet: Rc<SpriteSheet>) {
+        self.animator.set_sheet(sheet);
+    }
+
+    pub fn get_src_rect(&self) -> [f64; 4] {
+        self.animator.src_rect()
     }
 
     fn update_move(& mut self) -> bool {
@@ -88,55 +124,62 @@ impl Enemy {
             return false;
         }
 
-        if self.animation_start.elapsed() < Duration::from_millis(250) {
+        if self.move_elapsed < self.move_interval {
             return false;
         }
 
-        self.animation_start = Instant::now();
+        self.move_elapsed = Duration::ZERO;
         true
     }
 
-    pub fn move_toward_player(& mut self, player: &Player, window_size: Size) {
+    fn undo_blocked_move(& mut self, previous: Position, obstacles: &[Obstacle]) {
+        if obstacles.iter().any(|o| o.contains(self.position, self.width / 2)) {
+            self.position = previous;
+        }
+    }
+
+    pub fn move_toward_player(& mut self, player: &Player, arena_size: Size, obstacles: &[Obstacle]) {
         if self.update_move() {
-            let mut move_rate = 1;
-            if self.aggressive {
-                move_rate = 2;
-            }
+            let previous = self.position;
+            let move_rate = self.move_rate;
                 let player_pos = player.get_cur_position();
             if self.position.x != player_pos.x {
                 if self.position.x < player_pos.x {
-                    self.position.x = std::cmp::min(self.position.x + 1, window_size.width as i32 - self.width)
+                    self.position.x = std::cmp::min(self.position.x + 1, arena_size.width as i32 - self.width)
                 } else if self.position.x > player_pos.x {
-                    self.position.x = self.position.x - move_rate;
+                    self.position.x -= move_rate;
                 }
             }
             if self.position.y != player_pos.y{
                 if self.position.y < player_pos.y {
-                    self.position.y = std::cmp::min(self.position.y + 1, window_size.height as i32 - self.height);
+                    self.position.y = std::cmp::min(self.position.y + 1, arena_size.height as i32 - self.height);
                 } else if self.position.y > player_pos.y {
-                    self.position.y = self.position.y - move_rate;
+                    self.position.y -= move_rate;
                 }
             }
+            self.undo_blocked_move(previous, obstacles);
         }
     }
     
-    pub fn move_away_from_player(& mut self, player: &Player, window_size: Size) {
+    pub fn move_away_from_player(& mut self, player: &Player, arena_size: Size, obstacles: &[Obstacle]) {
         if self.update_move() {
+            let previous = self.position;
             let player_pos = player.get_cur_position();
             if self.position.x != player_pos.x {
                 if self.position.x < player_pos.x {
-                    self.position.x = self.position.x - 1;
+                    self.position.x -= 1;
                 } else if self.position.x > player_pos.x {
-                    self.position.x = std::cmp::min(self.position.x + 1, window_size.width as i32 - self.width);
+                    self.position.x = std::cmp::min(self.position.x + 1, arena_size.width as i32 - self.width);
                 }
             }
             if self.position.y != player_pos.y {
                 if self.position.y < player_pos.y {
-                    self.position.y = self.position.y - 1;
+                    self.position.y -= 1;
                 } else if self.position.y > player_pos.y {
-                    self.position.y = std::cmp::min(self.position.y + 1, window_size.height as i32 - self.height);
+                    self.position.y = std::cmp::min(self.position.y + 1, arena_size.height as i32 - self.height);
                 }
             }
+            self.undo_blocked_move(previous, obstacles);
         }
     }
 }
@@ -0,0 +1,120 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use ::image::ImageError;
+
+use super::replay::ReplayError;
+use super::save::SaveError;
+
+#[derive(Debug)]
+pub enum BoxesError {
+    NoDisplay(String),
+    Window(Vec<String>),
+    Image {
+        name: String,
+        source: ImageError,
+    },
+    Texture {
+        name: String,
+        reason: String,
+    },
+    Terminal(io::Error),
+    Save {
+        path: PathBuf,
+        source: ImageError,
+    },
+    Replay {
+        path: PathBuf,
+        source: ReplayError,
+    },
+    Network {
+        addr: String,
+        source: io::Error,
+    },
+    SaveGame {
+        path: PathBuf,
+        source: SaveError,
+    },
+    Bindings {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+impl BoxesError {
+    // A suggestion for the player on how to get the game running, printed after the error.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            BoxesError::NoDisplay(_) => {
+                "No display server was found. Run Boxes from a desktop session, or set DISPLAY or \
+                 WAYLAND_DISPLAY when connecting over SSH. `boxes tui` plays in the terminal instead."
+            },
+            BoxesError::Window(_) => {
+                "Boxes needs OpenGL 2.1 or newer. Update your graphics drivers, or try a software \
+                 renderer with LIBGL_ALWAYS_SOFTWARE=1."
+            },
+            BoxesError::Image { .. } => {
+                "Check the file in the assets directory or asset pack, or remove it to use the built in copy."
+            },
+            BoxesError::Texture { .. } => {
+                "The graphics driver rejected a texture. Updating your graphics drivers may help."
+            },
+            BoxesError::Terminal(_) => {
+                "The terminal could not be switched to raw mode. Run `boxes tui` from an interactive terminal."
+            },
+            BoxesError::Save { .. } => {
+                "Check that the output directory exists and is writable, and that the file name ends in .png or .gif."
+            },
+            BoxesError::Replay { .. } => {
+                "Recordings are made with `boxes --record <file>` and can only be replayed by the same version of Boxes."
+            },
+            BoxesError::Network { .. } => {
+                "Addresses are given as host:port, such as 127.0.0.1:7777. Check that the port isn't already in use, \
+                 and when connecting, that boxes-server or the peer is running on it."
+            },
+            BoxesError::SaveGame { .. } => {
+                "Saves are made with the quicksave key and can only be loaded by the same version of Boxes."
+            },
+            BoxesError::Bindings { .. } => {
+                "Fix the file, or delete it and choose keys again on the rebinding screen. The default keys \
+                 are used until then."
+            },
+        }
+    }
+}
+
+impl fmt::Display for BoxesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BoxesError::NoDisplay(reason) => write!(f, "failed to open a display: {}", reason),
+            BoxesError::Window(attempts) => {
+                write!(f, "failed to create a window: {}", attempts.join("; "))
+            },
+            BoxesError::Image { name, source } => write!(f, "failed to load {}: {}", name, source),
+            BoxesError::Texture { name, reason } => write!(f, "failed to create texture for {}: {}", name, reason),
+            BoxesError::Terminal(e) => write!(f, "terminal error: {}", e),
+            BoxesError::Save { path, source } => write!(f, "failed to save {}: {}", path.display(), source),
+            BoxesError::Replay { path, source } => write!(f, "failed to read recording {}: {}", path.display(), source),
+            BoxesError::Network { addr, source } => write!(f, "network error on {}: {}", addr, source),
+            BoxesError::SaveGame { path, source } => write!(f, "save file {}: {}", path.display(), source),
+            BoxesError::Bindings { path, source } => write!(f, "invalid key bindings {}: {}", path.display(), source),
+        }
+    }
+}
+
+impl Error for BoxesError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BoxesError::Image { source, .. } => Some(source),
+            BoxesError::Terminal(e) => Some(e),
+            BoxesError::Save { source, .. } => Some(source),
+            BoxesError::Replay { source, .. } => Some(source),
+            BoxesError::Network { source, .. } => Some(source),
+            BoxesError::SaveGame { source, .. } => Some(source),
+            BoxesError::Bindings { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use super::input::Action;
+use super::player::Direction;
+use super::replay::InputEvent;
+
+// How far the stick has to be pushed from the centre before it moves the player.
+const DEAD_ZONE: f32 = 0.35;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GamepadButton {
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    Start,
+    South,
+}
+
+impl GamepadButton {
+    fn action(&self) -> Action {
+        match self {
+            GamepadButton::DPadUp => Action::MoveUp,
+            GamepadButton::DPadDown => Action::MoveDown,
+            GamepadButton::DPadLeft => Action::MoveLeft,
+            GamepadButton::DPadRight => Action::MoveRight,
+            GamepadButton::Start => Action::Pause,
+            GamepadButton::South => Action::Confirm,
+        }
+    }
+}
+
+// Controller input independent of the library that reads the hardware, so events can
+// also be made up by hand. Stick values range from -1 to 1, with y increasing downwards
+// like the arena's coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GamepadEvent {
+    Pressed(GamepadButton),
+    Released(GamepadButton),
+    Stick {
+        x: f32,
+        y: f32,
+    },
+}
+
+// Turns gamepad events into presses and releases of the same actions the keyboard drives.
+// The analog stick is resolved to the direction of whichever axis is pushed furthest.
+pub struct Gamepad {
+    stick: Direction,
+}
+
+impl Gamepad {
+    pub fn new() -> Self {
+        Self {
+            stick: Direction::Stopped,
+        }
+    }
+
+    pub fn update(&mut self, event: GamepadEvent) -> Vec<InputEvent> {
+        match event {
+            GamepadEvent::Pressed(button) => vec![InputEvent::Pressed(button.action())],
+            GamepadEvent::Released(button) => vec![InputEvent::Released(button.action())],
+            GamepadEvent::Stick { x, y } => {
+                let direction = stick_direction(x, y);
+                if direction == self.stick {
+                    return vec![];
+                }
+
+                let mut events = vec![];
+                if let Some(action) = movement(self.stick) {
+                    events.push(InputEvent::Released(action));
+                }
+                if let Some(action) = movement(direction) {
+                    events.push(InputEvent::Pressed(action));
+                }
+                self.stick = direction;
+                events
+            },
+        }
+    }
+}
+
+fn stick_direction(x: f32, y: f32) -> Direction {
+    if x.hypot(y) < DEAD_ZONE {
+        Direction::Stopped
+    } else if x.abs() >= y.abs() {
+        if x > 0.0 { Direction::Right } else { Direction::Left }
+    } else if y > 0.0 {
+        Direction::Down
+    } else {
+        Direction::Up
+    }
+}
+
+// The stick's heading in degrees clockwise from the right, for analog movement.
+pub fn stick_heading(x: f32, y: f32) -> Option<f64> {
+    if x.hypot(y) < DEAD_ZONE {
+        None
+    } else {
+        Some((y as f64).atan2(x as f64).to_degrees().rem_euclid(360.0))
+    }
+}
+
+fn movement(direction: Direction) -> Option<Action> {
+    match direction {
+        Direction::Up => Some(Action::MoveUp),
+        Direction::Down => Some(Action::MoveDown),
+        Direction::Left => Some(Action::MoveLeft),
+        Direction::Right => Some(Action::MoveRight),
+        Direction::Stopped => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buttons_map_to_actions() {
+        let mut gamepad = Gamepad::new();
+        for (button, action) in [
+            (GamepadButton::DPadUp, Action::MoveUp),
+            (GamepadButton::DPadDown, Action::MoveDown),
+            (GamepadButton::DPadLeft, Action::MoveLeft),
+            (GamepadButton::DPadRight, Action::MoveRight),
+            (GamepadButton::Start, Action::Pause),
+            (GamepadButton::South, Action::Confirm),
+        ] {
+            assert_eq!(gamepad.update(GamepadEvent::Pressed(button)), vec![InputEvent::Pressed(action)]);
+            assert_eq!(gamepad.update(GamepadEvent::Released(button)), vec![InputEvent::Released(action)]);
+        }
+    }
+
+    #[test]
+    fn dead_zone_is_ignored() {
+        let mut gamepad = Gamepad::new();
+        assert!(gamepad.update(GamepadEvent::Stick { x: 0.2, y: -0.2 }).is_empty());
+        assert_eq!(stick_direction(0.0, DEAD_ZONE * 0.99), Direction::Stopped);
+        assert_eq!(stick_heading(0.1, 0.1), None);
+        assert_eq!(stick_direction(0.0, DEAD_ZONE), Direction::Down);
+    }
+
+    #[test]
+    fn stick_resolves_to_furthest_axis() {
+        assert_eq!(stick_direction(0.9, 0.5), Direction::Right);
+        assert_eq!(stick_direction(-0.9, 0.5), Direction::Left);
+        assert_eq!(stick_direction(0.3, 0.8), Direction::Down);
+        assert_eq!(stick_direction(0.3, -0.8), Direction::Up);
+        assert_eq!(stick_heading(0.0, 1.0), Some(90.0));
+        assert_eq!(stick_heading(-1.0, 0.0), Some(180.0));
+    }
+
+    // The stick holds at most one direction, releasing it before pressing the next.
+    #[test]
+    fn stick_presses_and_releases_like_the_dpad() {
+        let mut gamepad = Gamepad::new();
+        assert_eq!(gamepad.update(GamepadEvent::Stick { x: 1.0, y: 0.0 }), vec![InputEvent::Pressed(Action::MoveRight)]);
+        assert!(gamepad.update(GamepadEvent::Stick { x: 0.8, y: 0.1 }).is_empty());
+        assert_eq!(
+            gamepad.update(GamepadEvent::Stick { x: 0.0, y: -1.0 }),
+            vec![InputEvent::Released(Action::MoveRight), InputEvent::Pressed(Action::MoveUp)]
+        );
+        assert_eq!(gamepad.update(GamepadEvent::Stick { x: 0.0, y: 0.0 }), vec![InputEvent::Released(Action::MoveUp)]);
+    }
+}
@@ -0,0 +1,79 @@
+use gilrs::{
+    Axis,
+    Button,
+    Event,
+    EventType,
+    Gilrs
+};
+
+use super::gamepad::{
+    GamepadButton,
+    GamepadEvent
+};
+
+// Reads every connected controller through gilrs and reports them as one gamepad.
+pub struct GilrsInput {
+    gilrs: Gilrs,
+    stick: [f32; 2],
+}
+
+impl GilrsInput {
+    pub fn new() -> Option<Self> {
+        match Gilrs::new() {
+            Ok(gilrs) => Some(Self {
+                gilrs,
+                stick: [0.0; 2],
+            }),
+            Err(e) => {
+                println!("gamepads unavailable: {}", e);
+                None
+            },
+        }
+    }
+
+    pub fn poll(&mut self) -> Vec<GamepadEvent> {
+        let mut events = vec![];
+        while let Some(Event { event, .. }) = self.gilrs.next_event() {
+            match event {
+                EventType::ButtonPressed(button, _) => {
+                    if let Some(b) = gamepad_button(button) {
+                        events.push(GamepadEvent::Pressed(b));
+                    }
+                },
+                EventType::ButtonReleased(button, _) => {
+                    if let Some(b) = gamepad_button(button) {
+                        events.push(GamepadEvent::Released(b));
+                    }
+                },
+                // gilrs reports up as positive, the arena as negative.
+                EventType::AxisChanged(Axis::LeftStickX, value, _) => {
+                    self.stick[0] = value;
+                    events.push(GamepadEvent::Stick { x: self.stick[0], y: self.stick[1] });
+                },
+                EventType::AxisChanged(Axis::LeftStickY, value, _) => {
+                    self.stick[1] = -value;
+                    events.push(GamepadEvent::Stick { x: self.stick[0], y: self.stick[1] });
+                },
+                EventType::Disconnected => {
+                    self.stick = [0.0; 2];
+                    events.push(GamepadEvent::Stick { x: 0.0, y: 0.0 });
+                },
+                _ => {},
+            }
+        }
+
+        events
+    }
+}
+
+fn gamepad_button(button: Button) -> Option<GamepadButton> {
+    match button {
+        Button::DPadUp => Some(GamepadButton::DPadUp),
+        Button::DPadDown => Some(GamepadButton::DPadDown),
+        Button::DPadLeft => Some(GamepadButton::DPadLeft),
+        Button::DPadRight => Some(GamepadButton::DPadRight),
+        Button::Start => Some(GamepadButton::Start),
+        Button::South => Some(GamepadButton::South),
+        _ => None,
+    }
+}
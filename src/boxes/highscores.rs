@@ -0,0 +1,359 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{
+    Deserialize,
+    Serialize
+};
+
+// Bumped whenever scores change shape, since old tables can't be read into them.
+pub const VERSION: u32 = 1;
+pub const MAX_SCORES: usize = 10;
+pub const MAX_NAME: usize = 12;
+const HIGHSCORES_FILE: &str = "highscores.json";
+
+// One finished game. The date is in seconds since the Unix epoch and the replay is the
+// recording that was being made at the time, if there was one.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HighScore {
+    pub name: String,
+    pub date: u64,
+    pub level: u32,
+    pub caught: u32,
+    pub seed: u64,
+    #[serde(default)]
+    pub replay: Option<PathBuf>,
+}
+
+impl HighScore {
+    // A score from a game that has just ended, still waiting for a name.
+    pub fn new(level: u32, caught: u32, seed: u64, replay: Option<PathBuf>) -> Self {
+        Self {
+            name: String::new(),
+            date: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            level,
+            caught,
+            seed,
+            replay,
+        }
+    }
+
+    // Reaching a later level ranks higher, then catching more enemies. Ties go to the
+    // score that was there first.
+    fn beats(&self, other: &HighScore) -> bool {
+        (self.level, self.caught) > (other.level, other.caught)
+    }
+
+    pub fn get_date(&self) -> String {
+        let (year, month, day) = civil_date((self.date / 86400) as i64);
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+#[derive(Debug)]
+pub enum ScoreError {
+    Io(io::Error),
+    Format(serde_json::Error),
+    Version(u32),
+    Corrupt,
+}
+
+impl fmt::Display for ScoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScoreError::Io(e) => write!(f, "{}", e),
+            ScoreError::Format(e) => write!(f, "{}", e),
+            ScoreError::Version(v) => write!(f, "unsupported high score version {}", v),
+            ScoreError::Corrupt => write!(f, "checksum doesn't match, the file is corrupt"),
+        }
+    }
+}
+
+impl Error for ScoreError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ScoreError::Io(e) => Some(e),
+            ScoreError::Format(e) => Some(e),
+            ScoreError::Version(_) | ScoreError::Corrupt => None,
+        }
+    }
+}
+
+impl From<io::Error> for ScoreError {
+    fn from(e: io::Error) -> Self {
+        ScoreError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ScoreError {
+    fn from(e: serde_json::Error) -> Self {
+        ScoreError::Format(e)
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct ScoreFile {
+    version: u32,
+    // Of the scores as written, so a file that was damaged but still parses is noticed.
+    checksum: u64,
+    scores: Vec<HighScore>,
+}
+
+#[derive(Deserialize)]
+struct ScoreVersion {
+    version: u32,
+}
+
+// The best games played on this machine, best first.
+pub struct HighScores {
+    path: PathBuf,
+    scores: Vec<HighScore>,
+}
+
+impl HighScores {
+    // BOXES_HIGHSCORES if it is set, otherwise the user's data directory, falling back to
+    // the working directory on systems without one.
+    pub fn default_path() -> PathBuf {
+        if let Some(path) = std::env::var_os("BOXES_HIGHSCORES") {
+            return PathBuf::from(path);
+        }
+        dirs::data_dir()
+            .map(|dir| dir.join("boxes").join(HIGHSCORES_FILE))
+            .unwrap_or_else(|| PathBuf::from(HIGHSCORES_FILE))
+    }
+
+    // A table that can't be read is replaced by the backup kept when it was last saved, or
+    // failing that an empty one, so a bad file never stops the game from starting.
+    pub fn load(path: &Path) -> Self {
+        let backup = with_suffix(path, ".bak");
+        let scores = match read(path) {
+            Ok(scores) => scores,
+            Err(e) => {
+                if !is_missing(&e) {
+                    println!("invalid high scores {}: {}, trying the backup", path.display(), e);
+                }
+                match read(&backup) {
+                    Ok(scores) => {
+                        println!("restored high scores from {}", backup.display());
+                        scores
+                    },
+                    Err(e) if is_missing(&e) => vec![],
+                    Err(e) => {
+                        println!("invalid high scores {}: {}, starting a new table", backup.display(), e);
+                        vec![]
+                    },
+                }
+            },
+        };
+        Self { path: path.to_path_buf(), scores }
+    }
+
+    // Written to a temporary file which then replaces the table, so an interrupted save
+    // can't leave it half written. The table it replaces becomes the backup, as long as it
+    // was readable.
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = ScoreFile {
+            version: VERSION,
+            checksum: checksum(&self.scores),
+            scores: self.scores.clone(),
+        };
+        let temp = with_suffix(&self.path, ".tmp");
+        let mut out = File::create(&temp)?;
+        out.write_all(&serde_json::to_vec_pretty(&file)?)?;
+        out.sync_all()?;
+        if read(&self.path).is_ok() {
+            fs::rename(&self.path, with_suffix(&self.path, ".bak"))?;
+        }
+        fs::rename(&temp, &self.path)
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get_scores(&self) -> &[HighScore] {
+        &self.scores
+    }
+
+    // Where `score` would place in the table, if it makes it in at all.
+    pub fn rank(&self, score: &HighScore) -> Option<usize> {
+        let rank = self.scores.iter().position(|s| score.beats(s)).unwrap_or(self.scores.len());
+        (rank < MAX_SCORES).then_some(rank)
+    }
+
+    pub fn insert(&mut self, score: HighScore) -> Option<usize> {
+        let rank = self.rank(&score)?;
+        self.scores.insert(rank, score);
+        self.scores.truncate(MAX_SCORES);
+        Some(rank)
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<HighScore>, ScoreError> {
+    let json = fs::read(path)?;
+    let version = serde_json::from_slice::<ScoreVersion>(&json)?.version;
+    if version != VERSION {
+        return Err(ScoreError::Version(version));
+    }
+    let file = serde_json::from_slice::<ScoreFile>(&json)?;
+    if file.checksum != checksum(&file.scores) {
+        return Err(ScoreError::Corrupt);
+    }
+    Ok(file.scores)
+}
+
+fn is_missing(e: &ScoreError) -> bool {
+    matches!(e, ScoreError::Io(e) if e.kind() == io::ErrorKind::NotFound)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+// FNV-1a over the scores' JSON. Unlike the standard library's hasher it is the same in
+// every build, which a file kept between versions needs.
+fn checksum(scores: &[HighScore]) -> u64 {
+    serde_json::to_vec(scores).unwrap_or_default()
+        .iter()
+        .fold(0xcbf29ce484222325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+// The year, month and day `days` after 1970-01-01 in the proleptic Gregorian calendar,
+// using Howard Hinnant's civil_from_days.
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("boxes-scores-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn score(name: &str, level: u32, caught: u32) -> HighScore {
+        HighScore { name: name.to_string(), ..HighScore::new(level, caught, 7, None) }
+    }
+
+    fn names(scores: &HighScores) -> Vec<&str> {
+        scores.get_scores().iter().map(|s| s.name.as_str()).collect()
+    }
+
+    // A table saved twice, so there is a backup holding just "first".
+    fn saved_twice(dir: &Path) -> PathBuf {
+        let path = dir.join("scores.json");
+        let mut scores = HighScores::load(&path);
+        scores.insert(score("first", 2, 5));
+        scores.save().unwrap();
+        scores.insert(score("second", 3, 1));
+        scores.save().unwrap();
+        path
+    }
+
+    #[test]
+    fn ranked_by_level_then_caught() {
+        let mut scores = HighScores::load(Path::new("missing/scores.json"));
+        assert_eq!(scores.insert(score("a", 2, 5)), Some(0));
+        assert_eq!(scores.insert(score("b", 3, 0)), Some(0));
+        assert_eq!(scores.insert(score("c", 2, 9)), Some(1));
+        // Ties go to the score that got there first.
+        assert_eq!(scores.insert(score("d", 2, 5)), Some(3));
+        assert_eq!(names(&scores), ["b", "c", "a", "d"]);
+    }
+
+    #[test]
+    fn table_is_truncated() {
+        let mut scores = HighScores::load(Path::new("missing/scores.json"));
+        for i in 0..MAX_SCORES as u32 {
+            scores.insert(score(&i.to_string(), 5, i + 1));
+        }
+        assert_eq!(scores.rank(&score("worst", 5, 0)), None);
+        assert_eq!(scores.insert(score("worst", 5, 0)), None);
+        assert_eq!(scores.insert(score("best", 6, 0)), Some(0));
+        assert_eq!(scores.get_scores().len(), MAX_SCORES);
+        assert_eq!(scores.get_scores()[0].name, "best");
+        // The lowest score made way for it.
+        assert!(scores.get_scores().iter().all(|s| s.caught != 1));
+    }
+
+    #[test]
+    fn saves_replace_the_table_and_keep_a_backup() {
+        let dir = scratch("save");
+        let path = saved_twice(&dir);
+        assert!(!with_suffix(&path, ".tmp").exists());
+        assert_eq!(names(&HighScores::load(&path)), ["second", "first"]);
+        assert_eq!(read(&with_suffix(&path, ".bak")).unwrap().len(), 1);
+
+        // A save interrupted before the rename leaves the table as it was.
+        fs::write(with_suffix(&path, ".tmp"), "{ \"version\": 1, \"chec").unwrap();
+        assert_eq!(names(&HighScores::load(&path)), ["second", "first"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_table_falls_back_to_backup() {
+        let dir = scratch("corrupt");
+        let path = saved_twice(&dir);
+        let json = fs::read_to_string(&path).unwrap().replace("second", "cheater");
+        fs::write(&path, json).unwrap();
+        assert!(matches!(read(&path), Err(ScoreError::Corrupt)));
+        assert_eq!(names(&HighScores::load(&path)), ["first"]);
+
+        // Saving over a corrupt table keeps the good backup rather than replacing it.
+        HighScores::load(&path).save().unwrap();
+        assert_eq!(names(&HighScores::load(&with_suffix(&path, ".bak"))), ["first"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let dir = scratch("version");
+        let path = saved_twice(&dir);
+        let json = fs::read_to_string(&path).unwrap().replace("\"version\": 1", "\"version\": 2");
+        fs::write(&path, json).unwrap();
+        assert!(matches!(read(&path), Err(ScoreError::Version(2))));
+        assert_eq!(names(&HighScores::load(&path)), ["first"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unreadable_table_and_backup_start_empty() {
+        let dir = scratch("empty");
+        let path = saved_twice(&dir);
+        fs::write(&path, "not json").unwrap();
+        fs::write(with_suffix(&path, ".bak"), "").unwrap();
+        assert!(HighScores::load(&path).get_scores().is_empty());
+        assert!(HighScores::load(&dir.join("missing.json")).get_scores().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(HighScore { date: 0, ..score("a", 1, 0) }.get_date(), "1970-01-01");
+        assert_eq!(HighScore { date: 951_782_400, ..score("a", 1, 0) }.get_date(), "2000-02-29");
+        assert_eq!(HighScore { date: 1_792_368_000, ..score("a", 1, 0) }.get_date(), "2026-10-19");
+    }
+}
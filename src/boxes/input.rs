@@ -0,0 +1,274 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use piston_window::Key;
+use serde::{
+    Deserialize,
+    Serialize
+};
+
+use super::BoxesError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Pause,
+    Confirm,
+    Debug,
+    Fullscreen,
+    Rebind,
+    QuickSave,
+    QuickLoad,
+}
+
+impl Action {
+    // The order actions are listed in on the rebinding screen.
+    pub const ALL: [Action; 11] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Pause,
+        Action::Confirm,
+        Action::Debug,
+        Action::Fullscreen,
+        Action::Rebind,
+        Action::QuickSave,
+        Action::QuickLoad,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Action::MoveUp => "move up",
+            Action::MoveDown => "move down",
+            Action::MoveLeft => "move left",
+            Action::MoveRight => "move right",
+            Action::Pause => "pause",
+            Action::Confirm => "confirm",
+            Action::Debug => "debug",
+            Action::Fullscreen => "fullscreen",
+            Action::Rebind => "rebind keys",
+            Action::QuickSave => "quicksave",
+            Action::QuickLoad => "quickload",
+        }
+    }
+
+    pub fn is_movement(&self) -> bool {
+        matches!(self, Action::MoveUp | Action::MoveDown | Action::MoveLeft | Action::MoveRight)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    #[default]
+    Arrows,
+    Wasd,
+    Vim,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 3] = [Preset::Arrows, Preset::Wasd, Preset::Vim];
+
+    fn movement(&self) -> [Key; 4] {
+        match self {
+            Preset::Arrows => [Key::Up, Key::Down, Key::Left, Key::Right],
+            Preset::Wasd => [Key::W, Key::S, Key::A, Key::D],
+            Preset::Vim => [Key::K, Key::J, Key::H, Key::L],
+        }
+    }
+}
+
+// The bindings file names a preset to start from and, optionally, replacement keys for
+// some of its actions. The rebinding screen writes every action out in full.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct BindingsFile {
+    #[serde(default)]
+    preset: Preset,
+    #[serde(default)]
+    keys: BTreeMap<Action, Vec<Key>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Bindings {
+    preset: Preset,
+    keys: BTreeMap<Action, Vec<Key>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self::from_preset(Preset::default())
+    }
+}
+
+impl Bindings {
+    pub fn from_preset(preset: Preset) -> Self {
+        let [up, down, left, right] = preset.movement();
+        let keys = BTreeMap::from([
+            (Action::MoveUp, vec![up]),
+            (Action::MoveDown, vec![down]),
+            (Action::MoveLeft, vec![left]),
+            (Action::MoveRight, vec![right]),
+            (Action::Pause, vec![Key::P]),
+            (Action::Confirm, vec![Key::Return]),
+            (Action::Debug, vec![Key::F3]),
+            (Action::Fullscreen, vec![Key::F11]),
+            (Action::Rebind, vec![Key::F1]),
+            (Action::QuickSave, vec![Key::F5]),
+            (Action::QuickLoad, vec![Key::F9]),
+        ]);
+        Self {
+            preset,
+            keys,
+        }
+    }
+
+    // A missing file means the defaults. A broken one is an error, which callers report
+    // before falling back to the defaults themselves.
+    pub fn load(path: &Path) -> Result<Self, BoxesError> {
+        let json = match fs::read(path) {
+            Ok(json) => json,
+            Err(_) => return Ok(Self::from_preset(Preset::default())),
+        };
+        let file = serde_json::from_slice::<BindingsFile>(&json)
+            .map_err(|source| BoxesError::Bindings { path: path.to_path_buf(), source })?;
+        let mut bindings = Self::from_preset(file.preset);
+        for (action, keys) in file.keys {
+            bindings.keys.insert(action, vec![]);
+            for key in keys {
+                bindings.add(action, key);
+            }
+        }
+        Ok(bindings)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let file = BindingsFile {
+            preset: self.preset,
+            keys: self.keys.clone(),
+        };
+        fs::write(path, serde_json::to_string_pretty(&file)?)
+    }
+
+    pub fn get_preset(&self) -> Preset {
+        self.preset
+    }
+
+    pub fn action(&self, key: Key) -> Option<Action> {
+        self.keys.iter().find(|(_, keys)| keys.contains(&key)).map(|(action, _)| *action)
+    }
+
+    pub fn keys(&self, action: Action) -> &[Key] {
+        self.keys.get(&action).map(|k| k.as_slice()).unwrap_or_default()
+    }
+
+    // A key drives one action at a time, so binding it takes it away from any other.
+    fn add(&mut self, action: Action, key: Key) {
+        for keys in self.keys.values_mut() {
+            keys.retain(|k| *k != key);
+        }
+        self.keys.entry(action).or_default().push(key);
+    }
+
+    pub fn bind(&mut self, action: Action, key: Key) {
+        self.keys.insert(action, vec![]);
+        self.add(action, key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bindings_file(name: &str, json: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("boxes-bindings-{}-{}.json", name, std::process::id()));
+        fs::write(&path, json).unwrap();
+        path
+    }
+
+    #[test]
+    fn presets_bind_movement() {
+        for (preset, up, right) in [
+            (Preset::Arrows, Key::Up, Key::Right),
+            (Preset::Wasd, Key::W, Key::D),
+            (Preset::Vim, Key::K, Key::L),
+        ] {
+            let bindings = Bindings::from_preset(preset);
+            assert_eq!(bindings.get_preset(), preset);
+            assert_eq!(bindings.action(up), Some(Action::MoveUp));
+            assert_eq!(bindings.action(right), Some(Action::MoveRight));
+            assert_eq!(bindings.keys(Action::Pause), &[Key::P]);
+        }
+    }
+
+    #[test]
+    fn missing_file_uses_defaults() {
+        let path = std::env::temp_dir().join(format!("boxes-bindings-missing-{}.json", std::process::id()));
+        let bindings = Bindings::load(&path).unwrap();
+        assert_eq!(bindings.get_preset(), Preset::Arrows);
+        assert_eq!(bindings.keys(Action::MoveUp), &[Key::Up]);
+    }
+
+    #[test]
+    fn file_overrides_only_the_keys_it_lists() {
+        let json = format!(r#"{{"preset": "wasd", "keys": {{"Pause": [{}]}}}}"#, serde_json::to_string(&Key::Space).unwrap());
+        let path = bindings_file("partial", &json);
+        let bindings = Bindings::load(&path).unwrap();
+        assert_eq!(bindings.get_preset(), Preset::Wasd);
+        assert_eq!(bindings.keys(Action::Pause), &[Key::Space]);
+        assert_eq!(bindings.action(Key::P), None);
+        assert_eq!(bindings.keys(Action::MoveLeft), &[Key::A]);
+        assert_eq!(bindings.keys(Action::QuickSave), &[Key::F5]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn file_can_take_a_key_from_another_action() {
+        let json = format!(r#"{{"keys": {{"Confirm": [{}]}}}}"#, serde_json::to_string(&Key::Up).unwrap());
+        let path = bindings_file("taken", &json);
+        let bindings = Bindings::load(&path).unwrap();
+        assert_eq!(bindings.action(Key::Up), Some(Action::Confirm));
+        assert!(bindings.keys(Action::MoveUp).is_empty());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn binding_a_key_takes_it_from_its_previous_action() {
+        let mut bindings = Bindings::default();
+        bindings.bind(Action::Pause, Key::Left);
+        assert_eq!(bindings.keys(Action::Pause), &[Key::Left]);
+        assert_eq!(bindings.action(Key::Left), Some(Action::Pause));
+        assert_eq!(bindings.action(Key::P), None);
+        assert!(bindings.keys(Action::MoveLeft).is_empty());
+    }
+
+    #[test]
+    fn saved_bindings_load_back() {
+        let path = bindings_file("saved", "");
+        let mut bindings = Bindings::from_preset(Preset::Vim);
+        bindings.bind(Action::QuickSave, Key::S);
+        bindings.save(&path).unwrap();
+        let loaded = Bindings::load(&path).unwrap();
+        assert_eq!(loaded.get_preset(), Preset::Vim);
+        for action in Action::ALL {
+            assert_eq!(loaded.keys(action), bindings.keys(action));
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn malformed_file_is_an_error() {
+        let path = bindings_file("malformed", r#"{"preset": "dvorak"}"#);
+        match Bindings::load(&path) {
+            Err(BoxesError::Bindings { path: p, .. }) => assert_eq!(p, path),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("malformed bindings loaded"),
+        }
+        fs::remove_file(path).unwrap();
+    }
+}
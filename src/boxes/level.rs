@@ -0,0 +1,412 @@
+use std::collections::VecDeque;
+
+use piston::{
+    Position,
+    Size
+};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use serde::{
+    Deserialize,
+    Serialize
+};
+
+use super::save::PositionDef;
+use super::SPRITE_WIDTH;
+
+const MIN_SPAWN_DISTANCE: i32 = 200;
+const EDGE_MARGIN: i32 = 8;
+const CELL_SIZE: i32 = SPRITE_WIDTH / 2;
+const MAX_OBSTACLES: u32 = 6;
+const MAX_ATTEMPTS: u64 = 32;
+const MAX_SPAWN_TRIES: u32 = 1000;
+const WALL_THICKNESS: i32 = 8;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Archetype {
+    Wanderer,
+    Chaser,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct EnemySpawn {
+    #[serde(with = "PositionDef")]
+    pub position: Position,
+    pub archetype: Archetype,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct Obstacle {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Obstacle {
+    pub fn contains(&self, p: Position, margin: i32) -> bool {
+        (self.x - margin <= p.x) && (p.x <= self.x + self.width + margin) &&
+        (self.y - margin <= p.y) && (p.y <= self.y + self.height + margin)
+    }
+
+    fn overlaps(&self, other: &Obstacle, margin: i32) -> bool {
+        (self.x - margin < other.x + other.width) && (other.x < self.x + self.width + margin) &&
+        (self.y - margin < other.y + other.height) && (other.y < self.y + self.height + margin)
+    }
+}
+
+// Levels can also be hand written as JSON and shipped in an asset pack.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Level {
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default)]
+    pub difficulty: u32,
+    #[serde(default)]
+    pub enemies: Vec<EnemySpawn>,
+    #[serde(default)]
+    pub obstacles: Vec<Obstacle>,
+}
+
+pub struct LevelGenerator {
+    arena: Size,
+    player_start: Position,
+    start_enemies: i32,
+    max_enemies: i32,
+}
+
+impl LevelGenerator {
+    pub fn new(arena: Size, player_start: Position, start_enemies: i32, max_enemies: i32) -> Self {
+        Self {
+            arena,
+            player_start,
+            start_enemies,
+            max_enemies,
+        }
+    }
+
+    pub fn enemy_count(&self, difficulty: u32) -> i32 {
+        (self.start_enemies + difficulty as i32).min(self.max_enemies)
+    }
+
+    // Levels are regenerated from a derived seed until the solver accepts one. As a last
+    // resort enemies that can't be boxed in are moved, and obstacles taken away, most
+    // recently placed first, until they can. Any enemy that still can't is left out.
+    pub fn generate(&self, seed: u64, difficulty: u32) -> Level {
+        for attempt in 0..MAX_ATTEMPTS {
+            let level = self.generate_attempt(seed, difficulty, attempt);
+            if self.is_winnable(&level) {
+                return level;
+            }
+        }
+
+        let mut level = self.generate_attempt(seed, difficulty, MAX_ATTEMPTS);
+        let mut rng = StdRng::seed_from_u64(level_seed(seed, difficulty, MAX_ATTEMPTS + 1));
+        let (obstacles, enemies) = (level.obstacles.len(), level.enemies.len());
+        let mut moves = 0;
+        loop {
+            let reachable = self.reachable_cells(&level);
+            let open = |p: &Position| {
+                distance(*p, self.player_start) >= MIN_SPAWN_DISTANCE as f64 &&
+                    !level.obstacles.iter().any(|o| o.contains(*p, SPRITE_WIDTH)) &&
+                    self.can_box_in(&reachable, *p)
+            };
+            let mut moved = true;
+            for e in level.enemies.iter_mut().filter(|e| !self.can_box_in(&reachable, e.position)) {
+                match (0..MAX_SPAWN_TRIES).map(|_| self.spawn_position(&mut rng)).find(open) {
+                    Some(p) => {
+                        e.position = p;
+                        moves += 1;
+                    },
+                    None => moved = false,
+                }
+            }
+            if moved {
+                break;
+            }
+            if level.obstacles.pop().is_none() {
+                level.enemies.retain(|e| self.can_box_in(&reachable, e.position));
+                break;
+            }
+        }
+        println!(
+            "no winnable level for seed {} at difficulty {}, moved enemies {} times, removed {} of {} obstacles and {} of {} enemies",
+            seed,
+            difficulty,
+            moves,
+            obstacles - level.obstacles.len(),
+            obstacles,
+            enemies - level.enemies.len(),
+            enemies
+        );
+        level
+    }
+
+    // Somewhere an enemy could spawn. In an arena too small to keep enemies clear of the
+    // edges it is always the same spot, which the solver then rejects.
+    fn spawn_position(&self, rng: &mut StdRng) -> Position {
+        let min = SPRITE_WIDTH + EDGE_MARGIN;
+        let (max_x, max_y) = ((self.arena.width as i32 - min).max(min), (self.arena.height as i32 - min).max(min));
+        Position {
+            x: rng.gen_range(min..=max_x),
+            y: rng.gen_range(min..=max_y),
+        }
+    }
+
+    fn generate_attempt(&self, seed: u64, difficulty: u32, attempt: u64) -> Level {
+        let mut rng = StdRng::seed_from_u64(level_seed(seed, difficulty, attempt));
+        let chaser_chance = (0.25 + 0.075 * difficulty as f64).min(0.75);
+
+        let mut obstacles: Vec<Obstacle> = vec![];
+        for _ in 0..(difficulty / 2).min(MAX_OBSTACLES) {
+            let (width, height) = if rng.gen_bool(0.5) {
+                (rng.gen_range(CELL_SIZE..=SPRITE_WIDTH * 3), rng.gen_range(CELL_SIZE..=SPRITE_WIDTH * 3))
+            } else if rng.gen_bool(0.5) {
+                (rng.gen_range(SPRITE_WIDTH * 2..=SPRITE_WIDTH * 6), WALL_THICKNESS)
+            } else {
+                (WALL_THICKNESS, rng.gen_range(SPRITE_WIDTH * 2..=SPRITE_WIDTH * 6))
+            };
+            // Obstacles too big for a small arena are left out.
+            let (max_x, max_y) = (self.arena.width as i32 - SPRITE_WIDTH - width, self.arena.height as i32 - SPRITE_WIDTH - height);
+            if max_x < SPRITE_WIDTH || max_y < SPRITE_WIDTH {
+                continue;
+            }
+            let obstacle = Obstacle {
+                x: rng.gen_range(SPRITE_WIDTH..=max_x),
+                y: rng.gen_range(SPRITE_WIDTH..=max_y),
+                width,
+                height,
+            };
+            if obstacle.contains(self.player_start, MIN_SPAWN_DISTANCE / 2) ||
+                obstacles.iter().any(|o| o.overlaps(&obstacle, SPRITE_WIDTH)) {
+                continue;
+            }
+            obstacles.push(obstacle);
+        }
+
+        let mut enemies: Vec<EnemySpawn> = vec![];
+        let mut tries = 0;
+        while enemies.len() < self.enemy_count(difficulty) as usize && tries < MAX_SPAWN_TRIES {
+            tries += 1;
+            let position = self.spawn_position(&mut rng);
+            if distance(position, self.player_start) < MIN_SPAWN_DISTANCE as f64 ||
+                obstacles.iter().any(|o| o.contains(position, SPRITE_WIDTH)) {
+                continue;
+            }
+            let archetype = if rng.gen_bool(chaser_chance) {
+                Archetype::Chaser
+            } else {
+                Archetype::Wanderer
+            };
+            enemies.push(EnemySpawn { position, archetype });
+        }
+
+        Level {
+            seed,
+            difficulty,
+            enemies,
+            obstacles,
+        }
+    }
+
+    // A level is winnable when every enemy can be boxed in from its spawn point: the
+    // square around it must fit in the arena and its whole perimeter must be reachable
+    // from the player start without crossing an obstacle.
+    pub fn is_winnable(&self, level: &Level) -> bool {
+        let reachable = self.reachable_cells(level);
+        level.enemies.iter().all(|e| self.can_box_in(&reachable, e.position))
+    }
+
+    fn can_box_in(&self, reachable: &[bool], position: Position) -> bool {
+        let half = SPRITE_WIDTH;
+        let (left, top) = (position.x - half, position.y - half);
+        let (right, bottom) = (position.x + half, position.y + half);
+        if left < EDGE_MARGIN || top < EDGE_MARGIN ||
+            right > self.arena.width as i32 - EDGE_MARGIN || bottom > self.arena.height as i32 - EDGE_MARGIN {
+            return false;
+        }
+
+        let mut perimeter = vec![];
+        let mut offset = 0;
+        while offset <= half * 2 {
+            perimeter.push(Position { x: left + offset, y: top });
+            perimeter.push(Position { x: left + offset, y: bottom });
+            perimeter.push(Position { x: left, y: top + offset });
+            perimeter.push(Position { x: right, y: top + offset });
+            offset += CELL_SIZE;
+        }
+        perimeter.iter().all(|p| match self.cell(*p) {
+            Some(i) => reachable[i],
+            None => false,
+        })
+    }
+
+    fn columns(&self) -> i32 {
+        self.arena.width as i32 / CELL_SIZE + 1
+    }
+
+    fn rows(&self) -> i32 {
+        self.arena.height as i32 / CELL_SIZE + 1
+    }
+
+    fn cell(&self, p: Position) -> Option<usize> {
+        let (col, row) = (p.x / CELL_SIZE, p.y / CELL_SIZE);
+        if p.x < 0 || p.y < 0 || col >= self.columns() || row >= self.rows() {
+            return None;
+        }
+
+        Some((row * self.columns() + col) as usize)
+    }
+
+    fn reachable_cells(&self, level: &Level) -> Vec<bool> {
+        let (columns, rows) = (self.columns(), self.rows());
+        let mut reachable = vec![false; (columns * rows) as usize];
+        let blocked = |col: i32, row: i32| {
+            let p = Position { x: col * CELL_SIZE, y: row * CELL_SIZE };
+            level.obstacles.iter().any(|o| o.contains(p, EDGE_MARGIN))
+        };
+
+        let mut queue = VecDeque::new();
+        if let Some(start) = self.cell(self.player_start) {
+            reachable[start] = true;
+            queue.push_back((self.player_start.x / CELL_SIZE, self.player_start.y / CELL_SIZE));
+        }
+        while let Some((col, row)) = queue.pop_front() {
+            for (dc, dr) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+                let (c, r) = (col + dc, row + dr);
+                if c < 0 || r < 0 || c >= columns || r >= rows || blocked(c, r) {
+                    continue;
+                }
+                let i = (r * columns + c) as usize;
+                if !reachable[i] {
+                    reachable[i] = true;
+                    queue.push_back((c, r));
+                }
+            }
+        }
+
+        reachable
+    }
+}
+
+// Each attempt at each difficulty gets a stream of its own. Adding the inputs together
+// would give attempt k at difficulty d the same stream as attempt k - 1 at d + 1.
+fn level_seed(seed: u64, difficulty: u32, attempt: u64) -> u64 {
+    splitmix(splitmix(splitmix(seed) ^ difficulty as u64) ^ attempt)
+}
+
+// The SplitMix64 finalizer, which spreads nearby inputs across the whole range.
+fn splitmix(z: u64) -> u64 {
+    let z = z.wrapping_add(0x9e3779b97f4a7c15);
+    let z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+fn distance(a: Position, b: Position) -> f64 {
+    (((a.x - b.x).pow(2) + (a.y - b.y).pow(2)) as f64).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generator(width: u32, height: u32) -> LevelGenerator {
+        LevelGenerator::new(Size::from([width, height]), Position { x: SPRITE_WIDTH / 2, y: SPRITE_WIDTH / 2 }, 1, 10)
+    }
+
+    fn level(enemies: &[(i32, i32)], obstacles: Vec<Obstacle>) -> Level {
+        Level {
+            seed: 0,
+            difficulty: 0,
+            enemies: enemies.iter()
+                .map(|&(x, y)| EnemySpawn { position: Position { x, y }, archetype: Archetype::Wanderer })
+                .collect(),
+            obstacles,
+        }
+    }
+
+    #[test]
+    fn same_seed_generates_same_level() {
+        let generator = generator(800, 800);
+        for difficulty in [0, 3, 8, 12] {
+            let a = serde_json::to_string(&generator.generate(42, difficulty)).unwrap();
+            let b = serde_json::to_string(&generator.generate(42, difficulty)).unwrap();
+            assert_eq!(a, b);
+        }
+    }
+
+    #[test]
+    fn difficulties_dont_share_attempts() {
+        for seed in 0..100 {
+            for difficulty in 0..10 {
+                for attempt in 1..MAX_ATTEMPTS {
+                    assert_ne!(level_seed(seed, difficulty, attempt), level_seed(seed, difficulty + 1, attempt - 1));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn generated_levels_are_winnable() {
+        let generator = generator(800, 800);
+        for seed in 0..50 {
+            for difficulty in [0, 4, 12] {
+                let level = generator.generate(seed, difficulty);
+                assert!(generator.is_winnable(&level), "seed {} difficulty {}", seed, difficulty);
+                assert_eq!(level.enemies.len(), generator.enemy_count(difficulty) as usize);
+            }
+        }
+    }
+
+    #[test]
+    fn small_arenas_dont_panic() {
+        for (width, height) in [(64, 64), (100, 800), (800, 100), (1, 1)] {
+            let generator = generator(width, height);
+            for difficulty in [0, 12] {
+                let level = generator.generate(7, difficulty);
+                assert!(generator.is_winnable(&level), "{}x{} difficulty {}", width, height, difficulty);
+            }
+        }
+    }
+
+    #[test]
+    fn unwinnable_enemies_are_moved() {
+        // Crowded enough with walls that a few seeds never get a winnable attempt.
+        let generator = generator(340, 340);
+        let mut fallbacks = 0;
+        for seed in 0..50 {
+            let level = generator.generate(seed, 12);
+            assert!(generator.is_winnable(&level), "seed {}", seed);
+            if (0..MAX_ATTEMPTS).all(|a| !generator.is_winnable(&generator.generate_attempt(seed, 12, a))) {
+                fallbacks += 1;
+                assert_eq!(level.enemies.len(), generator.generate_attempt(seed, 12, MAX_ATTEMPTS).enemies.len());
+            }
+        }
+        assert!(fallbacks > 0);
+    }
+
+    #[test]
+    fn open_arena_is_winnable() {
+        assert!(generator(800, 800).is_winnable(&level(&[(400, 400), (700, 100)], vec![])));
+    }
+
+    #[test]
+    fn enemy_against_the_edge_is_not_winnable() {
+        assert!(!generator(800, 800).is_winnable(&level(&[(SPRITE_WIDTH / 2, 400)], vec![])));
+    }
+
+    #[test]
+    fn walled_in_enemy_is_not_winnable() {
+        let walls = vec![
+            Obstacle { x: 300, y: 300, width: 200, height: WALL_THICKNESS },
+            Obstacle { x: 300, y: 492, width: 200, height: WALL_THICKNESS },
+            Obstacle { x: 300, y: 300, width: WALL_THICKNESS, height: 200 },
+            Obstacle { x: 492, y: 300, width: WALL_THICKNESS, height: 200 },
+        ];
+        let generator = generator(800, 800);
+        assert!(!generator.is_winnable(&level(&[(400, 400)], walls.clone())));
+        assert!(generator.is_winnable(&level(&[(650, 650)], walls)));
+    }
+}
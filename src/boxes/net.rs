@@ -0,0 +1,474 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use piston::Position;
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::{
+    Deserialize,
+    Serialize
+};
+
+use super::replay::InputEvent;
+use super::view::View;
+use super::{
+    Boxes,
+    BoxesError
+};
+
+// The server simulates at a fixed rate and sends every client a snapshot every other tick.
+pub(super) const TICK: Duration = Duration::from_micros(8333);
+const SNAPSHOT_EVERY: u64 = 2;
+// How often a client that has nothing to send reminds the server it is still there, and
+// how long the server waits without hearing from a client before freeing its player.
+const HEARTBEAT: Duration = Duration::from_millis(250);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_DATAGRAM: usize = 65507;
+// Snapshots are sent as JSON text in parts of at most this many bytes. Escaping can at
+// most double a part, which still leaves room for the rest of the message.
+const SNAPSHOT_PART: usize = 30000;
+
+// Simulated network conditions, applied to everything a side sends: each datagram is held
+// back for `latency` and dropped with probability `loss`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Conditions {
+    pub latency: Duration,
+    pub loss: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub enum ClientMessage {
+    Join,
+    // Every input the server hasn't acknowledged yet, numbered in the order they were
+    // made, so inputs lost on the way are sent again with the next message.
+    Input(Vec<(u64, InputEvent)>),
+    Leave,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub enum ServerMessage {
+    Welcome {
+        player: usize,
+    },
+    Full,
+    // The view as JSON, split into `parts` so a busy arena still fits in datagrams. A
+    // snapshot is drawn once every part of it has arrived.
+    Snapshot {
+        tick: u64,
+        // The last of this client's inputs the server has applied.
+        ack: u64,
+        part: usize,
+        parts: usize,
+        view: String,
+    },
+    // Something the server couldn't send, so the client can say why it has stopped updating.
+    Error(String),
+}
+
+// The parts of the newest snapshot received so far.
+struct Parts {
+    tick: u64,
+    parts: Vec<Option<String>>,
+}
+
+// A UDP socket that sends JSON messages under simulated network conditions.
+pub(super) struct Link {
+    socket: UdpSocket,
+    conditions: Conditions,
+    queue: VecDeque<(Instant, SocketAddr, Vec<u8>)>,
+}
+
+impl Link {
+    pub(super) fn bind(addr: &str, conditions: Conditions) -> io::Result<Self> {
+        Self::on(UdpSocket::bind(addr)?, conditions)
+    }
+
+    pub(super) fn on(socket: UdpSocket, conditions: Conditions) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            conditions,
+            queue: VecDeque::new(),
+        })
+    }
+
+    pub(super) fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    // Returns false if the message couldn't be sent at all. Messages lost to the simulated
+    // conditions count as sent.
+    pub(super) fn send<T: Serialize>(&mut self, to: SocketAddr, message: &T) -> bool {
+        let bytes = match serde_json::to_vec(message) {
+            Ok(bytes) if bytes.len() <= MAX_DATAGRAM => bytes,
+            Ok(bytes) => {
+                println!("message to {} too large to send: {} bytes", to, bytes.len());
+                return false;
+            },
+            Err(e) => {
+                println!("failed to encode message to {}: {}", to, e);
+                return false;
+            },
+        };
+        if rand::thread_rng().gen_bool(self.conditions.loss.clamp(0.0, 1.0)) {
+            return true;
+        }
+
+        self.queue.push_back((Instant::now() + self.conditions.latency, to, bytes));
+        self.flush();
+        true
+    }
+
+    // Sends whatever has been held back long enough.
+    pub(super) fn flush(&mut self) {
+        let now = Instant::now();
+        while self.queue.front().is_some_and(|(due, _, _)| *due <= now) {
+            if let Some((_, to, bytes)) = self.queue.pop_front() {
+                if let Err(e) = self.socket.send_to(&bytes, to) {
+                    println!("failed to send to {}: {}", to, e);
+                }
+            }
+        }
+    }
+
+    pub(super) fn recv<T: DeserializeOwned>(&mut self) -> Option<(SocketAddr, T)> {
+        let mut buf = vec![0; MAX_DATAGRAM];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, from)) => match serde_json::from_slice(&buf[..len]) {
+                    Ok(message) => return Some((from, message)),
+                    Err(e) => println!("invalid message from {}: {}", from, e),
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return None,
+                // A client that went away without saying so makes some platforms report
+                // the next read as a reset connection.
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(e) => {
+                    println!("failed to receive: {}", e);
+                    return None;
+                },
+            }
+        }
+    }
+}
+
+struct Client {
+    player: usize,
+    acked: u64,
+    last_heard: Instant,
+}
+
+// The client side of a game played on a server. Input is sent to the server rather than
+// applied locally, and the server's latest snapshot is drawn in place of the local game.
+pub struct Remote {
+    link: Link,
+    server: SocketAddr,
+    player: Option<usize>,
+    pending: Vec<(u64, InputEvent)>,
+    sequence: u64,
+    last_waypoint: Option<Position>,
+    view: Option<View>,
+    tick: u64,
+    parts: Option<Parts>,
+    error: Option<String>,
+    last_sent: Option<Instant>,
+}
+
+impl Remote {
+    pub fn connect(addr: &str, conditions: Conditions) -> io::Result<Self> {
+        let server = addr.to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address found"))?;
+        let local = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        Ok(Self {
+            link: Link::bind(local, conditions)?,
+            server,
+            player: None,
+            pending: vec![],
+            sequence: 0,
+            last_waypoint: None,
+            view: None,
+            tick: 0,
+            parts: None,
+            error: None,
+            last_sent: None,
+        })
+    }
+
+    pub fn send(&mut self, event: InputEvent) {
+        match event {
+            InputEvent::Waypoint { x, y } => self.last_waypoint = Some(Position { x, y }),
+            InputEvent::ClearWaypoints => self.last_waypoint = None,
+            _ => {},
+        }
+        self.sequence += 1;
+        self.pending.push((self.sequence, event));
+        self.send_pending();
+    }
+
+    fn send_pending(&mut self) {
+        let message = match self.player {
+            Some(_) => ClientMessage::Input(self.pending.clone()),
+            None => ClientMessage::Join,
+        };
+        self.link.send(self.server, &message);
+        self.last_sent = Some(Instant::now());
+    }
+
+    // Takes in whatever the server has sent and keeps the connection alive.
+    pub fn poll(&mut self) {
+        while let Some((from, message)) = self.link.recv::<ServerMessage>() {
+            if from != self.server {
+                continue;
+            }
+            match message {
+                ServerMessage::Welcome { player } => {
+                    if self.player.is_none() {
+                        println!("joined {} as player {}", self.server, player + 1);
+                    }
+                    self.player = Some(player);
+                },
+                ServerMessage::Full => println!("{} has no free players", self.server),
+                ServerMessage::Snapshot { tick, ack, part, parts, view } => {
+                    self.pending.retain(|(sequence, _)| *sequence > ack);
+                    self.receive_part(tick, part, parts, view);
+                },
+                ServerMessage::Error(e) => {
+                    println!("{}: {}", self.server, e);
+                    self.error = Some(e);
+                },
+            }
+        }
+
+        let quiet = self.last_sent.is_none_or(|t| t.elapsed() >= HEARTBEAT);
+        if quiet || (self.player.is_some() && !self.pending.is_empty()) {
+            self.send_pending();
+        }
+        self.link.flush();
+    }
+
+    // Snapshots can arrive out of order, so parts of ones older than the newest drawn or
+    // being put together are ignored.
+    fn receive_part(&mut self, tick: u64, part: usize, parts: usize, json: String) {
+        if (tick <= self.tick && self.view.is_some()) || part >= parts {
+            return;
+        }
+        let buffer = match self.parts.as_mut() {
+            Some(buffer) if buffer.tick > tick => return,
+            Some(buffer) if buffer.tick == tick && buffer.parts.len() == parts => buffer,
+            _ => self.parts.insert(Parts { tick, parts: vec![None; parts] }),
+        };
+        buffer.parts[part] = Some(json);
+        if buffer.parts.iter().any(|p| p.is_none()) {
+            return;
+        }
+
+        let json = buffer.parts.iter().flatten().map(|p| p.as_str()).collect::<String>();
+        self.parts = None;
+        match serde_json::from_str(&json) {
+            Ok(view) => {
+                self.tick = tick;
+                self.view = Some(view);
+                self.error = None;
+            },
+            Err(e) => println!("invalid snapshot from {}: {}", self.server, e),
+        }
+    }
+
+    pub fn get_view(&self) -> Option<&View> {
+        self.view.as_ref()
+    }
+
+    pub fn get_last_waypoint(&self) -> Option<Position> {
+        self.last_waypoint
+    }
+
+    pub fn get_error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
+
+impl Drop for Remote {
+    // Said straight away rather than through the simulated conditions, since there is no
+    // later chance to send it.
+    fn drop(&mut self) {
+        if let Ok(bytes) = serde_json::to_vec(&ClientMessage::Leave) {
+            let _ = self.link.socket.send_to(&bytes, self.server);
+        }
+    }
+}
+
+impl Boxes {
+    // Runs the game without a front end as the authority for clients on `addr`. Each
+    // client that joins takes the next free player, and everyone is sent what the game
+    // looks like as it plays out.
+    pub fn serve(&mut self, addr: &str, conditions: Conditions) -> Result<(), BoxesError> {
+        let network_error = |source| BoxesError::Network { addr: addr.to_string(), source };
+        let mut link = Link::bind(addr, conditions).map_err(network_error)?;
+        println!("serving {} players on {}", self.player_count(), link.local_addr().map_err(network_error)?);
+
+        let mut clients: HashMap<SocketAddr, Client> = HashMap::new();
+        let mut tick = 0;
+        let mut next_tick = Instant::now();
+        loop {
+            while let Some((from, message)) = link.recv::<ClientMessage>() {
+                match message {
+                    ClientMessage::Join => {
+                        let free = (0..self.player_count()).find(|p| !clients.values().any(|c| c.player == *p));
+                        match clients.get(&from).map(|c| c.player).or(free) {
+                            Some(player) => {
+                                clients.entry(from).or_insert_with(|| {
+                                    println!("{} joined as player {}", from, player + 1);
+                                    Client { player, acked: 0, last_heard: Instant::now() }
+                                });
+                                link.send(from, &ServerMessage::Welcome { player });
+                            },
+                            None => {
+                                link.send(from, &ServerMessage::Full);
+                            },
+                        }
+                    },
+                    ClientMessage::Input(inputs) => {
+                        let client = match clients.get_mut(&from) {
+                            Some(c) => c,
+                            None => continue,
+                        };
+                        client.last_heard = Instant::now();
+                        let player = client.player;
+                        // Anything else, such as saving or rebinding keys, is for the server's
+                        // own operator, and pausing is for the host.
+                        for (sequence, event) in inputs {
+                            if sequence > client.acked {
+                                client.acked = sequence;
+                                if Self::is_client_input(player, event) {
+                                    self.apply_input(player, event);
+                                }
+                            }
+                        }
+                    },
+                    ClientMessage::Leave => {
+                        if let Some(client) = clients.remove(&from) {
+                            println!("player {} left", client.player + 1);
+                            self.players[client.player].player.stop();
+                        }
+                    },
+                }
+            }
+
+            let timed_out = clients.iter()
+                .filter(|(_, c)| c.last_heard.elapsed() >= CLIENT_TIMEOUT)
+                .map(|(addr, _)| *addr)
+                .collect::<Vec<_>>();
+            for addr in timed_out {
+                if let Some(client) = clients.remove(&addr) {
+                    println!("player {} timed out", client.player + 1);
+                    self.players[client.player].player.stop();
+                }
+            }
+
+            self.step(TICK);
+            self.update_spectators();
+            tick += 1;
+            if tick % SNAPSHOT_EVERY == 0 && !clients.is_empty() {
+                match serde_json::to_string(&self.view()) {
+                    Ok(view) => {
+                        let parts = split(&view, SNAPSHOT_PART);
+                        for (addr, client) in clients.iter() {
+                            send_snapshot(&mut link, *addr, tick, client.acked, &parts);
+                        }
+                    },
+                    Err(e) => println!("failed to encode snapshot: {}", e),
+                }
+            }
+            link.flush();
+
+            // Falling behind skips the missed ticks rather than running them all at once.
+            next_tick += TICK;
+            let now = Instant::now();
+            if next_tick > now {
+                thread::sleep(next_tick - now);
+            } else {
+                next_tick = now;
+            }
+        }
+    }
+}
+
+fn send_snapshot(link: &mut Link, to: SocketAddr, tick: u64, ack: u64, parts: &[&str]) {
+    for (part, view) in parts.iter().enumerate() {
+        let message = ServerMessage::Snapshot { tick, ack, part, parts: parts.len(), view: view.to_string() };
+        if !link.send(to, &message) {
+            link.send(to, &ServerMessage::Error(format!("snapshot {} could not be sent", tick)));
+            return;
+        }
+    }
+}
+
+// Splits `text` into pieces of at most `len` bytes without breaking up a character.
+fn split(text: &str, len: usize) -> Vec<&str> {
+    let mut pieces = vec![];
+    let mut rest = text;
+    while rest.len() > len {
+        let mut end = len;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (piece, tail) = rest.split_at(end);
+        pieces.push(piece);
+        rest = tail;
+    }
+    pieces.push(rest);
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A view far too big for one datagram, like a long game with many trails would give.
+    fn large_view() -> View {
+        let mut view = Boxes::new(800, 800, 1, 10).view();
+        view.players[0].lines = (0..10000).map(|i| [i as f64, 1.5, i as f64 + 0.25, 799.75]).collect();
+        view
+    }
+
+    #[test]
+    fn split_keeps_characters_whole() {
+        let text = "aé".repeat(10);
+        let pieces = split(&text, 4);
+        assert!(pieces.iter().all(|p| p.len() <= 4));
+        assert_eq!(pieces.concat(), text);
+        assert_eq!(split("", 4), vec![""]);
+    }
+
+    #[test]
+    fn large_snapshots_fit_in_datagrams() {
+        let json = serde_json::to_string(&large_view()).unwrap();
+        assert!(json.len() > MAX_DATAGRAM);
+        let parts = split(&json, SNAPSHOT_PART);
+        for (part, view) in parts.iter().enumerate() {
+            let message = ServerMessage::Snapshot { tick: u64::MAX, ack: u64::MAX, part, parts: parts.len(), view: view.to_string() };
+            assert!(serde_json::to_vec(&message).unwrap().len() <= MAX_DATAGRAM);
+        }
+    }
+
+    #[test]
+    fn parts_are_put_back_together_in_any_order() {
+        let view = large_view();
+        let json = serde_json::to_string(&view).unwrap();
+        let parts = split(&json, SNAPSHOT_PART);
+        let mut remote = Remote::connect("127.0.0.1:9", Conditions::default()).unwrap();
+        for part in (0..parts.len()).rev() {
+            assert!(remote.get_view().is_none());
+            remote.receive_part(4, part, parts.len(), parts[part].to_string());
+        }
+        assert_eq!(serde_json::to_string(remote.get_view().unwrap()).unwrap(), json);
+
+        // Parts of an older snapshot don't replace it.
+        remote.receive_part(2, 0, 1, serde_json::to_string(&Boxes::new(800, 800, 1, 10).view()).unwrap());
+        assert_eq!(remote.get_view().unwrap().players[0].lines.len(), 10000);
+    }
+}
@@ -0,0 +1,80 @@
+use std::rc::Rc;
+
+use piston_window::{
+    clear,
+    color,
+    Context,
+    G2d,
+    G2dTexture,
+    Line
+};
+use sprite::Sprite;
+
+use super::render::{
+    triangulate,
+    Color,
+    Renderer,
+    SpriteId
+};
+use super::viewport::Viewport;
+
+// Draws in arena units through the viewport transform, so the arena is letterboxed into
+// whatever size the window has.
+pub struct PistonRenderer<'a, 'b> {
+    context: Context,
+    viewport: Viewport,
+    graphics: &'a mut G2d<'b>,
+    hero: Rc<G2dTexture>,
+    enemy: Rc<G2dTexture>,
+}
+
+impl<'a, 'b> PistonRenderer<'a, 'b> {
+    pub fn new(context: Context, viewport: Viewport, graphics: &'a mut G2d<'b>, hero: Rc<G2dTexture>, enemy: Rc<G2dTexture>) -> Self {
+        Self {
+            context: Context { transform: viewport.transform(context.transform), ..context },
+            viewport,
+            graphics,
+            hero,
+            enemy,
+        }
+    }
+}
+
+impl Renderer for PistonRenderer<'_, '_> {
+    // Only the arena takes the clear colour; the bars around it are black.
+    fn clear(&mut self, color: Color) {
+        clear(color::BLACK, self.graphics);
+        let arena = self.viewport.get_arena();
+        piston_window::rectangle(color, [0.0, 0.0, arena.width, arena.height], self.context.transform, self.graphics);
+    }
+
+    fn draw_rect(&mut self, color: Color, rect: [f64; 4]) {
+        piston_window::rectangle(color, rect, self.context.transform, self.graphics);
+    }
+
+    fn draw_line(&mut self, color: Color, radius: f64, from: [f64; 2], to: [f64; 2]) {
+        Line::new_round(color, radius)
+            .draw_from_to(from, to, &self.context.draw_state, self.context.transform, self.graphics);
+    }
+
+    // Piston fills polygons as a triangle fan, which only works for convex shapes, and
+    // captured loops rarely are.
+    fn draw_polygon(&mut self, color: Color, polygon: &[[f64; 2]]) {
+        for triangle in triangulate(polygon) {
+            piston_window::polygon(color, &triangle, self.context.transform, self.graphics);
+        }
+    }
+
+    fn draw_sprite(&mut self, sprite: SpriteId, src_rect: [f64; 4], position: [f64; 2], rotation: f64, tint: Color) {
+        let texture = match sprite {
+            SpriteId::Hero => self.hero.clone(),
+            SpriteId::Enemy => self.enemy.clone(),
+        };
+        let mut sprite = Sprite::from_texture_rect(texture, src_rect);
+        sprite.set_position(position[0], position[1]);
+        sprite.set_rotation(rotation);
+        sprite.set_color(tint[0], tint[1], tint[2]);
+        sprite.set_opacity(tint[3]);
+        sprite.draw(self.context.transform, self.graphics);
+    }
+}
@@ -1,11 +1,38 @@
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::Duration;
+
 use piston::Position;
-use piston_window::Key;
+use serde::{
+    Deserialize,
+    Serialize
+};
 
-use crate::boxes::Boxes;
+use crate::boxes::{
+    debug_enabled,
+    Boxes
+};
 
+use super::animation::{
+    Animator,
+    SpriteSheet
+};
 use super::enemy::Enemy;
+use super::input::Action;
+use super::save::{
+    positions,
+    PositionDef
+};
+
+// How long a turn that can't be made yet, because an obstacle or the edge of the arena is
+// in the way, keeps being retried.
+const TURN_BUFFER: Duration = Duration::from_millis(150);
+// Analog headings are rounded to this many degrees, so a wavering stick doesn't start a
+// new trail segment every tick.
+const ANALOG_STEP: f64 = 15.0;
+const HEADING_EPSILON: f64 = 0.01;
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
 pub enum Direction {
     Up,
     Down,
@@ -14,46 +41,119 @@ pub enum Direction {
     Stopped,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+// Cardinal movement follows the axes one direction at a time. Eight way movement combines
+// held directions into diagonals and analog movement steers by the gamepad stick, both
+// leaving trail segments at any angle.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Movement {
+    #[default]
+    Cardinal,
+    EightWay,
+    Analog,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
 pub enum PlayerState {
     Alive,
     Dead
 }
 
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Player {
+    #[serde(with = "PositionDef")]
     cur_position: Position,
+    #[serde(with = "PositionDef")]
     prev_position: Position,
+    exact_position: [f64; 2],
     cur_direction: Direction,
-    prev_direction: Direction,
+    heading: Option<f64>,
+    prev_heading: Option<f64>,
+    movement: Movement,
+    stick: Option<f64>,
+    held: Vec<Direction>,
+    turn: Option<Direction>,
+    turn_left: Duration,
+    #[serde(with = "positions")]
+    waypoints: VecDeque<Position>,
     state: PlayerState,
     height: i32,
     width: i32,
+    animator: Animator,
 }
 
 impl Player {
-    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+    pub fn new(x: i32, y: i32, width: i32, height: i32, sheet: Rc<SpriteSheet>) -> Self {
         Self {
             cur_position: Position { x, y },
             prev_position: Position { x, y },
+            exact_position: [x as f64, y as f64],
             cur_direction: Direction::Stopped,
-            prev_direction: Direction::Stopped,  
+            heading: None,
+            prev_heading: None,
+            movement: Movement::Cardinal,
+            stick: None,
+            held: vec![],
+            turn: None,
+            turn_left: Duration::ZERO,
+            waypoints: VecDeque::new(),
             state: PlayerState::Alive,
-            height: height,
-            width: width,
+            height,
+            width,
+            animator: Animator::new(sheet, "idle"),
         }
     }
 
     pub fn reset(&mut self, x: i32, y: i32) {
         self.cur_position = Position { x, y };
         self.prev_position = self.cur_position;
+        self.exact_position = [x as f64, y as f64];
         self.state = PlayerState::Alive;
+        self.waypoints.clear();
+    }
+
+    pub fn stop(&mut self) {
+        self.cur_direction = Direction::Stopped;
+        self.heading = None;
+        self.stick = None;
+        self.held.clear();
+        self.turn = None;
+        self.waypoints.clear();
+    }
+
+    pub fn set_movement(&mut self, movement: Movement) {
+        self.movement = movement;
+    }
+
+    pub fn get_movement(&self) -> Movement {
+        self.movement
+    }
+
+    // The analog stick's heading in degrees, or `None` while it is centred.
+    pub fn steer(&mut self, heading: Option<f64>) {
+        self.stick = heading.map(|h| (h / ANALOG_STEP).round() * ANALOG_STEP);
+        if heading.is_some() {
+            self.waypoints.clear();
+        }
+    }
+
+    pub fn add_waypoint(&mut self, p: Position) {
+        self.waypoints.push_back(p);
+    }
+
+    pub fn clear_waypoints(&mut self) {
+        self.waypoints.clear();
+    }
+
+    pub fn get_waypoints(&self) -> &VecDeque<Position> {
+        &self.waypoints
     }
 
     pub fn dead(&mut self) {
         self.state = PlayerState::Dead;
     }
 
-    pub fn is_dead(&mut self) -> bool {
+    pub fn is_dead(&self) -> bool {
         self.state == PlayerState::Dead
     }
 
@@ -71,14 +171,15 @@ impl Player {
             x: (self.cur_position.x + self.width) - (self.width / 4),
             y: (self.cur_position.y - self.height) + (self.height / 4),
         };
-        if (p1.x >= e1.x && p1.x <= e2.x) || (p2.x >= e1.x && p2.x <= e2.x) {
-            if (p1.y <= e1.y && p1.y >= e2.y) || (p2.y <= e1.y && p2.y >= e2.y) {
+        if ((p1.x >= e1.x && p1.x <= e2.x) || (p2.x >= e1.x && p2.x <= e2.x)) &&
+            ((p1.y <= e1.y && p1.y >= e2.y) || (p2.y <= e1.y && p2.y >= e2.y)) {
+            if debug_enabled() {
                 println!("====================");
                 println!("player {} {} {} {}", p1.x, p1.y, p2.x, p2.y);
                 println!("enemy  {} {} {} {}", e1.x, e1.y, e2.x, e2.y);
                 println!("====================");
-                return true;
             }
+            return true;
         }
 
         false
@@ -87,17 +188,28 @@ impl Player {
     pub fn list_state(&self) {
         println!("prev dir: {:?}", self.cur_direction);
         println!("cur dir: {:?}", self.cur_direction);
+        println!("heading: {:?} prev heading: {:?}", self.heading, self.prev_heading);
         println!("cur pos: {},{}", self.cur_position.x, self.cur_position.y);
     }
 
-    pub fn get_cur_direction(&self) -> Direction {
-        self.cur_direction
+    // The heading in degrees the player last moved in, clockwise from facing right, which
+    // it keeps facing while stopped.
+    pub fn get_facing(&self) -> f64 {
+        self.heading.or(self.prev_heading).unwrap_or(0.0)
     }
 
     pub fn get_cur_position(&self) -> Position {
         self.cur_position
     }
 
+    pub fn set_sprite_sheet(&mut self, sheet: Rc<SpriteSheet>) {
+        self.animator.set_sheet(sheet);
+    }
+
+    pub fn get_src_rect(&self) -> [f64; 4] {
+        self.animator.src_rect()
+    }
+
     pub fn get_prev_position(&self) -> Position {
         self.prev_position
     }
@@ -107,95 +219,332 @@ impl Player {
     }
 
     pub fn is_moving(&self) -> bool {
-        self.cur_direction != Direction::Stopped
+        self.heading.is_some()
     }
 
+    // Turning back the way the player came keeps to the same line, so only a change of
+    // heading other than a reversal starts a new trail segment.
     pub fn changed_axis(&self) -> bool {
-        println!("changed axis {:?} {:?}", self.prev_direction, self.cur_direction);
-        let changed_axis = match self.prev_direction {
-            Direction::Down | Direction::Up => {
-                match self.cur_direction {
-                    Direction::Left | Direction::Right => {
-                        true
-                    },
-                    _ => { false },
-                }
+        if debug_enabled() {
+            println!("changed axis {:?} {:?}", self.prev_heading, self.heading);
+        }
+
+        match (self.prev_heading, self.heading) {
+            (Some(prev), Some(cur)) => {
+                let turn = (cur - prev).rem_euclid(180.0);
+                turn > HEADING_EPSILON && turn < 180.0 - HEADING_EPSILON
             },
-            Direction::Left | Direction::Right => {
-                match self.cur_direction {
-                    Direction::Down | Direction::Up => {
-                        true
-                    },
-                    _ => { false },
-                }
+            _ => false,
+        }
+    }
+
+    fn next_position(&self, direction: Direction, boxes: &Boxes) -> Position {
+        let mut p = self.cur_position;
+        match direction {
+            Direction::Up => p.y -= 1,
+            Direction::Down => p.y += 1,
+            Direction::Left => p.x -= 1,
+            Direction::Right => p.x += 1,
+            Direction::Stopped => {},
+        }
+
+        p.x = p.x.min(boxes.arena_size.width as i32 - 8).max(8);
+        p.y = p.y.min(boxes.arena_size.height as i32 - 8).max(8);
+        if boxes.is_blocked(p) {
+            return self.cur_position;
+        }
+
+        p
+    }
+
+    fn is_open(p: [f64; 2], boxes: &Boxes) -> bool {
+        let rounded = Position { x: p[0].round() as i32, y: p[1].round() as i32 };
+        p[0] >= 8.0 && p[1] >= 8.0 &&
+            p[0] <= boxes.arena_size.width - 8.0 && p[1] <= boxes.arena_size.height - 8.0 &&
+            !boxes.is_blocked(rounded)
+    }
+
+    // Moves one pixel along `heading`, sliding along whichever axis is still open when an
+    // obstacle or the edge of the arena is in the way. Returns whether the player moved.
+    fn move_along(&mut self, heading: f64, boxes: &Boxes) -> bool {
+        let (sin, cos) = heading.to_radians().sin_cos();
+        let [x, y] = self.exact_position;
+        let candidates = [[x + cos, y + sin], [x + cos, y], [x, y + sin]];
+        match candidates.into_iter().find(|p| Self::is_open(*p, boxes)) {
+            Some(p) => {
+                self.exact_position = p;
+                self.cur_position = Position { x: p[0].round() as i32, y: p[1].round() as i32 };
+                true
             },
-            _ => { false }
+            None => false,
+        }
+    }
+
+    // Held directions add up, so two keys at right angles give a diagonal. With nothing
+    // held the player heads straight for the next waypoint.
+    fn free_heading(&mut self) -> Option<f64> {
+        if let (Movement::Analog, Some(stick)) = (self.movement, self.stick) {
+            return Some(stick);
+        }
+
+        let (x, y) = self.held.iter().fold((0, 0), |(x, y), d| match d {
+            Direction::Up => (x, y - 1),
+            Direction::Down => (x, y + 1),
+            Direction::Left => (x - 1, y),
+            Direction::Right => (x + 1, y),
+            Direction::Stopped => (x, y),
+        });
+        if x != 0 || y != 0 {
+            return Some((y as f64).atan2(x as f64).to_degrees().rem_euclid(360.0));
+        }
+
+        while let Some(target) = self.waypoints.front() {
+            let dx = target.x as f64 - self.exact_position[0];
+            let dy = target.y as f64 - self.exact_position[1];
+            if dx.hypot(dy) >= 1.0 {
+                return Some(dy.atan2(dx).to_degrees().rem_euclid(360.0));
+            }
+            self.waypoints.pop_front();
+        }
+
+        None
+    }
+
+    // Heads for the first waypoint along the axes, carrying on in the current direction
+    // while it still closes the distance so each leg has at most one turn. Waypoints that
+    // have been reached or can't be got any closer to are dropped.
+    fn follow_waypoints(&mut self, boxes: &Boxes) -> Direction {
+        while let Some(target) = self.waypoints.front().copied() {
+            let horizontal = match target.x - self.cur_position.x {
+                dx if dx > 0 => Direction::Right,
+                dx if dx < 0 => Direction::Left,
+                _ => Direction::Stopped,
+            };
+            let vertical = match target.y - self.cur_position.y {
+                dy if dy > 0 => Direction::Down,
+                dy if dy < 0 => Direction::Up,
+                _ => Direction::Stopped,
+            };
+            let y_further = (target.y - self.cur_position.y).abs() > (target.x - self.cur_position.x).abs();
+            let options = if self.cur_direction == vertical || (self.cur_direction != horizontal && y_further) {
+                [vertical, horizontal]
+            } else {
+                [horizontal, vertical]
+            };
+
+            let open = options.into_iter()
+                .filter(|d| *d != Direction::Stopped)
+                .find(|d| self.next_position(*d, boxes) != self.cur_position);
+            match open {
+                Some(d) => return d,
+                None => {
+                    self.waypoints.pop_front();
+                },
+            }
+        }
+
+        Direction::Stopped
+    }
+
+    fn cardinal_direction(&mut self, boxes: &Boxes, dt: Duration) -> Direction {
+        let mut direction = match self.held.last() {
+            Some(d) => *d,
+            None => self.follow_waypoints(boxes),
         };
+        if let Some(turn) = self.turn {
+            if self.next_position(turn, boxes) != self.cur_position {
+                direction = turn;
+                self.turn = None;
+            } else {
+                self.turn_left = self.turn_left.saturating_sub(dt);
+                if self.turn_left.is_zero() {
+                    self.turn = None;
+                } else {
+                    direction = self.cur_direction;
+                }
+            }
+        }
 
-        changed_axis
+        direction
     }
 
-    pub fn update(&self, boxes: &Boxes) -> Player
+    // In cardinal movement the most recently pressed direction that is still held wins. A
+    // new turn is applied on the next tick, or, while it can't be made, the player keeps
+    // going the way they were until it can or the turn buffer runs out.
+    pub fn update(&self, boxes: &Boxes, dt: Duration) -> Player
     {
         let mut player = Player{
             prev_position: self.cur_position,
-            ..*self
+            ..self.clone()
         };
 
-        match self.cur_direction {
-            Direction::Up => player.cur_position.y -= 1,
-            Direction::Down => player.cur_position.y += 1,
-            Direction::Left => player.cur_position.x -= 1,
-            Direction::Right => player.cur_position.x += 1,
-            Direction::Stopped => {},
-        }
+        let heading = match self.movement {
+            Movement::Cardinal => {
+                let direction = player.cardinal_direction(boxes, dt);
+                player.cur_position = self.next_position(direction, boxes);
+                player.exact_position = [player.cur_position.x as f64, player.cur_position.y as f64];
+                player.cur_direction = direction;
+                heading(direction)
+            },
+            Movement::EightWay | Movement::Analog => {
+                let heading = player.free_heading();
+                if let Some(h) = heading {
+                    // A waypoint that can't be got any closer to is given up on.
+                    if !player.move_along(h, boxes) && player.held.is_empty() && player.stick.is_none() {
+                        player.waypoints.pop_front();
+                    }
+                }
+                heading
+            },
+        };
 
-        player.cur_position.x = player.cur_position.x.min(boxes.window_size.width as i32 - 8).max(8);
-        player.cur_position.y = player.cur_position.y.min(boxes.window_size.height as i32 - 8).max(8);
-        if player.cur_direction != Direction::Stopped {
-            player.prev_direction = player.cur_direction;
+        if self.heading.is_some() {
+            player.prev_heading = self.heading;
         }
+        player.heading = heading;
+        if heading.is_some() {
+            player.animator.play("walk");
+        } else {
+            player.animator.play("idle");
+        }
+        player.animator.update(dt);
 
         player
     }
 
-    pub fn button_pressed(&mut self, key: &Key) {
-        match key {
-            Key::Up => {self.cur_direction = Direction::Up},
-            Key::Down => {self.cur_direction = Direction::Down},
-            Key::Left => {self.cur_direction = Direction::Left},
-            Key::Right => {self.cur_direction = Direction::Right},
-            _ => {},
+    pub fn action_pressed(&mut self, action: &Action) {
+        if let Some(d) = direction(action) {
+            self.waypoints.clear();
+            self.held.retain(|h| *h != d);
+            self.held.push(d);
+            self.turn = Some(d);
+            self.turn_left = TURN_BUFFER;
         }
     }
 
-    pub fn button_released(&mut self, key: &Key) {
-        match key {
-            Key::Up => {
-                match self.cur_direction {
-                    Direction::Up => { self.cur_direction = Direction::Stopped },
-                    _ => {},
-                }
-            },
-            Key::Down => {
-                match self.cur_direction {
-                    Direction::Down => { self.cur_direction = Direction::Stopped },
-                    _ => {},
-                }
-            },
-            Key::Left => {
-                match self.cur_direction {
-                    Direction::Left => { self.cur_direction = Direction::Stopped },
-                    _ => {},
-                }
-            },
-            Key::Right => {
-                match self.cur_direction {
-                    Direction::Right => { self.cur_direction = Direction::Stopped },
-                    _ => {},
-                }
-            },
-            _ => {},
+    pub fn action_released(&mut self, action: &Action) {
+        if let Some(d) = direction(action) {
+            self.held.retain(|h| *h != d);
+            if self.turn == Some(d) {
+                self.turn = None;
+            }
+        }
+    }
+}
+
+fn heading(direction: Direction) -> Option<f64> {
+    match direction {
+        Direction::Right => Some(0.0),
+        Direction::Down => Some(90.0),
+        Direction::Left => Some(180.0),
+        Direction::Up => Some(270.0),
+        Direction::Stopped => None,
+    }
+}
+
+fn direction(action: &Action) -> Option<Direction> {
+    match action {
+        Action::MoveUp => Some(Direction::Up),
+        Action::MoveDown => Some(Direction::Down),
+        Action::MoveLeft => Some(Direction::Left),
+        Action::MoveRight => Some(Direction::Right),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::boxes::level::Obstacle;
+    use crate::boxes::set_debug;
+
+    use super::*;
+
+    const TICK: Duration = Duration::from_micros(4167);
+
+    // An open arena, or one with a wall whose bottom edge players can run along at y 89.
+    fn arena(wall_width: Option<i32>) -> Boxes {
+        set_debug(false);
+        let mut boxes = Boxes::new(400, 400, 1, 10);
+        boxes.level.obstacles = wall_width.map(|width| Obstacle { x: 0, y: 0, width, height: 80 }).into_iter().collect();
+        boxes
+    }
+
+    fn player(x: i32, y: i32) -> Player {
+        Player::new(x, y, 32, 32, Rc::new(SpriteSheet::default()))
+    }
+
+    fn tick(player: &mut Player, boxes: &Boxes, ticks: u32) {
+        for _ in 0..ticks {
+            *player = player.update(boxes, TICK);
         }
     }
+
+    fn position(player: &Player) -> (i32, i32) {
+        (player.cur_position.x, player.cur_position.y)
+    }
+
+    #[test]
+    fn latest_held_key_wins() {
+        let boxes = arena(None);
+        let mut player = player(100, 100);
+        player.action_pressed(&Action::MoveRight);
+        tick(&mut player, &boxes, 1);
+        assert_eq!(position(&player), (101, 100));
+
+        player.action_pressed(&Action::MoveDown);
+        tick(&mut player, &boxes, 1);
+        assert_eq!(position(&player), (101, 101));
+
+        // Letting go goes back to the key still held, then to standing still.
+        player.action_released(&Action::MoveDown);
+        tick(&mut player, &boxes, 1);
+        assert_eq!(position(&player), (102, 101));
+        player.action_released(&Action::MoveRight);
+        tick(&mut player, &boxes, 1);
+        assert_eq!(position(&player), (102, 101));
+    }
+
+    #[test]
+    fn blocked_turn_is_made_once_it_fits() {
+        let boxes = arena(Some(200));
+        let mut player = player(190, 89);
+        player.action_pressed(&Action::MoveRight);
+        tick(&mut player, &boxes, 1);
+        player.action_pressed(&Action::MoveUp);
+        // The wall and its margin end at x 208, so the turn fits at 209.
+        tick(&mut player, &boxes, 18);
+        assert_eq!(position(&player), (209, 89));
+        tick(&mut player, &boxes, 1);
+        assert_eq!(position(&player), (209, 88));
+    }
+
+    #[test]
+    fn blocked_turn_expires() {
+        let boxes = arena(Some(300));
+        let mut player = player(100, 89);
+        player.action_pressed(&Action::MoveRight);
+        tick(&mut player, &boxes, 1);
+        player.action_pressed(&Action::MoveUp);
+        tick(&mut player, &boxes, 100);
+        // Kept going for the turn buffer, then up, still held, won and the wall stopped it.
+        let (x, y) = position(&player);
+        assert_eq!(y, 89);
+        assert!(x > 130 && x < 140, "stopped at {}", x);
+        tick(&mut player, &boxes, 10);
+        assert_eq!(position(&player), (x, y));
+    }
+
+    #[test]
+    fn releasing_before_turn_keeps_going() {
+        let boxes = arena(Some(200));
+        let mut player = player(190, 89);
+        player.action_pressed(&Action::MoveRight);
+        tick(&mut player, &boxes, 1);
+        player.action_pressed(&Action::MoveUp);
+        player.action_released(&Action::MoveRight);
+        tick(&mut player, &boxes, 18);
+        assert_eq!(position(&player), (209, 89));
+        tick(&mut player, &boxes, 1);
+        assert_eq!(position(&player), (209, 88));
+    }
 }
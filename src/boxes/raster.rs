@@ -0,0 +1,168 @@
+use ::image::{
+    Rgba,
+    RgbaImage
+};
+
+use super::render::{
+    Color,
+    Renderer,
+    SpriteId
+};
+
+// Draws frames into an image on the CPU, so screenshots can be taken without a window or
+// a GPU. Shapes are sampled at pixel centres and blended with their alpha, which is close
+// enough to the piston output to compare frames by eye.
+pub struct RasterRenderer {
+    frame: RgbaImage,
+    hero: RgbaImage,
+    enemy: RgbaImage,
+}
+
+impl RasterRenderer {
+    pub fn new(width: u32, height: u32, hero: RgbaImage, enemy: RgbaImage) -> Self {
+        Self {
+            frame: RgbaImage::new(width, height),
+            hero,
+            enemy,
+        }
+    }
+
+    pub fn frame(&self) -> &RgbaImage {
+        &self.frame
+    }
+
+    pub fn into_frame(self) -> RgbaImage {
+        self.frame
+    }
+
+    fn blend_pixel(&mut self, x: i64, y: i64, color: Color) {
+        if x < 0 || y < 0 || x >= self.frame.width() as i64 || y >= self.frame.height() as i64 {
+            return;
+        }
+
+        let pixel = self.frame.get_pixel_mut(x as u32, y as u32);
+        let a = color[3].clamp(0.0, 1.0);
+        for (channel, c) in pixel.0.iter_mut().zip(color).take(3) {
+            let src = c.clamp(0.0, 1.0) * 255.0;
+            *channel = (src * a + *channel as f32 * (1.0 - a)).round() as u8;
+        }
+        pixel.0[3] = 255;
+    }
+
+    // Visits the pixels whose centres fall inside the given bounds.
+    fn fill_bounds(&mut self, color: Color, min: [f64; 2], max: [f64; 2], inside: impl Fn([f64; 2]) -> bool) {
+        let x0 = (min[0] - 0.5).ceil().max(0.0) as i64;
+        let y0 = (min[1] - 0.5).ceil().max(0.0) as i64;
+        let x1 = (max[0] - 0.5).floor().min(self.frame.width() as f64 - 1.0) as i64;
+        let y1 = (max[1] - 0.5).floor().min(self.frame.height() as f64 - 1.0) as i64;
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                if inside([x as f64 + 0.5, y as f64 + 0.5]) {
+                    self.blend_pixel(x, y, color);
+                }
+            }
+        }
+    }
+}
+
+impl Renderer for RasterRenderer {
+    fn clear(&mut self, color: Color) {
+        let pixel = Rgba([
+            (color[0] * 255.0).round() as u8,
+            (color[1] * 255.0).round() as u8,
+            (color[2] * 255.0).round() as u8,
+            255
+        ]);
+        for p in self.frame.pixels_mut() {
+            *p = pixel;
+        }
+    }
+
+    fn draw_rect(&mut self, color: Color, rect: [f64; 4]) {
+        self.fill_bounds(color, [rect[0], rect[1]], [rect[0] + rect[2], rect[1] + rect[3]], |_| true);
+    }
+
+    // A line with round caps covers every pixel within `radius` of the segment.
+    fn draw_line(&mut self, color: Color, radius: f64, from: [f64; 2], to: [f64; 2]) {
+        let min = [from[0].min(to[0]) - radius, from[1].min(to[1]) - radius];
+        let max = [from[0].max(to[0]) + radius, from[1].max(to[1]) + radius];
+        self.fill_bounds(color, min, max, |p| segment_distance(p, from, to) <= radius);
+    }
+
+    fn draw_polygon(&mut self, color: Color, polygon: &[[f64; 2]]) {
+        if polygon.len() < 3 {
+            return;
+        }
+
+        let min = polygon.iter().fold([f64::MAX; 2], |m, p| [m[0].min(p[0]), m[1].min(p[1])]);
+        let max = polygon.iter().fold([f64::MIN; 2], |m, p| [m[0].max(p[0]), m[1].max(p[1])]);
+        self.fill_bounds(color, min, max, |p| contains(polygon, p));
+    }
+
+    // Maps each destination pixel back into the source frame, rotating about the sprite's
+    // centre, and copies the nearest texel.
+    fn draw_sprite(&mut self, sprite: SpriteId, src_rect: [f64; 4], position: [f64; 2], rotation: f64, tint: Color) {
+        let (sin, cos) = rotation.to_radians().sin_cos();
+        let half = [src_rect[2] / 2.0, src_rect[3] / 2.0];
+        let reach = half[0].hypot(half[1]);
+        let x0 = (position[0] - reach).floor() as i64;
+        let y0 = (position[1] - reach).floor() as i64;
+        let x1 = (position[0] + reach).ceil() as i64;
+        let y1 = (position[1] + reach).ceil() as i64;
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let dx = x as f64 + 0.5 - position[0];
+                let dy = y as f64 + 0.5 - position[1];
+                let u = dx * cos + dy * sin + half[0];
+                let v = -dx * sin + dy * cos + half[1];
+                if u < 0.0 || v < 0.0 || u >= src_rect[2] || v >= src_rect[3] {
+                    continue;
+                }
+
+                let texture = match sprite {
+                    SpriteId::Hero => &self.hero,
+                    SpriteId::Enemy => &self.enemy,
+                };
+                let sx = (src_rect[0] + u) as u32;
+                let sy = (src_rect[1] + v) as u32;
+                if sx >= texture.width() || sy >= texture.height() {
+                    continue;
+                }
+
+                let texel = texture.get_pixel(sx, sy).0;
+                let color = [
+                    texel[0] as f32 / 255.0 * tint[0],
+                    texel[1] as f32 / 255.0 * tint[1],
+                    texel[2] as f32 / 255.0 * tint[2],
+                    texel[3] as f32 / 255.0 * tint[3]
+                ];
+                self.blend_pixel(x, y, color);
+            }
+        }
+    }
+}
+
+fn segment_distance(p: [f64; 2], from: [f64; 2], to: [f64; 2]) -> f64 {
+    let d = [to[0] - from[0], to[1] - from[1]];
+    let len = d[0] * d[0] + d[1] * d[1];
+    let t = if len == 0.0 {
+        0.0
+    } else {
+        (((p[0] - from[0]) * d[0] + (p[1] - from[1]) * d[1]) / len).clamp(0.0, 1.0)
+    };
+    (p[0] - from[0] - d[0] * t).hypot(p[1] - from[1] - d[1] * t)
+}
+
+fn contains(polygon: &[[f64; 2]], p: [f64; 2]) -> bool {
+    let mut inside = false;
+    let mut j = polygon.len() - 1;
+    for i in 0..polygon.len() {
+        let (a, b) = (polygon[i], polygon[j]);
+        if (a[1] > p[1]) != (b[1] > p[1]) && p[0] < (b[0] - a[0]) * (p[1] - a[1]) / (b[1] - a[1]) + a[0] {
+            inside = !inside;
+        }
+        j = i;
+    }
+
+    inside
+}
@@ -0,0 +1,169 @@
+use super::font;
+
+pub type Color = [f32; 4];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpriteId {
+    Hero,
+    Enemy,
+}
+
+// Drawing operations the game needs from a backend. Coordinates are arena pixels with the
+// origin in the top left corner; sprites are positioned by their centre.
+pub trait Renderer {
+    fn clear(&mut self, color: Color);
+
+    fn draw_rect(&mut self, color: Color, rect: [f64; 4]);
+
+    fn draw_line(&mut self, color: Color, radius: f64, from: [f64; 2], to: [f64; 2]);
+
+    fn draw_polygon(&mut self, color: Color, polygon: &[[f64; 2]]);
+
+    // The sprite's colours are multiplied by `tint`, so white draws it unchanged.
+    fn draw_sprite(&mut self, sprite: SpriteId, src_rect: [f64; 4], position: [f64; 2], rotation: f64, tint: Color);
+
+    // Renders the built in bitmap font one rectangle per lit pixel. Backends with real
+    // font support can override this.
+    fn draw_text(&mut self, color: Color, text: &str, position: [f64; 2], scale: f64) {
+        for (x, y) in font::pixels(text) {
+            self.draw_rect(color, [
+                position[0] + x as f64 * scale,
+                position[1] + y as f64 * scale,
+                scale,
+                scale
+            ]);
+        }
+    }
+}
+
+// Splits a simple polygon of either winding into triangles by ear clipping, for backends
+// that can only fill convex shapes. Loops that cross themselves may leave part unfilled
+// rather than fill outside the loop.
+pub fn triangulate(polygon: &[[f64; 2]]) -> Vec<[[f64; 2]; 3]> {
+    let mut points = polygon.to_vec();
+    if points.len() > 1 && points.first() == points.last() {
+        points.pop();
+    }
+    let area = (0..points.len())
+        .map(|i| cross([0.0, 0.0], points[i], points[(i + 1) % points.len()]))
+        .sum::<f64>();
+    if points.len() < 3 || area == 0.0 {
+        return vec![];
+    }
+
+    // Corners that turn the same way as the polygon as a whole are convex.
+    let winding = area.signum();
+    let mut remaining = (0..points.len()).collect::<Vec<_>>();
+    let mut triangles = vec![];
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let corner = |i: usize| (points[remaining[(i + n - 1) % n]], points[remaining[i]], points[remaining[(i + 1) % n]]);
+        let ear = (0..n).find(|&i| {
+            let (a, b, c) = corner(i);
+            cross(a, b, c) * winding > 0.0 && remaining.iter().all(|&j| {
+                let p = points[j];
+                p == a || p == b || p == c || !in_triangle(p, a, b, c)
+            })
+        });
+        match ear {
+            Some(i) => {
+                let (a, b, c) = corner(i);
+                triangles.push([a, b, c]);
+                remaining.remove(i);
+            },
+            // Points along a straight edge are never ears but can go without changing the
+            // shape. Without any, the loop crosses itself and can't be clipped further.
+            None => match (0..n).find(|&i| {
+                let (a, b, c) = corner(i);
+                cross(a, b, c) == 0.0
+            }) {
+                Some(i) => {
+                    remaining.remove(i);
+                },
+                None => break,
+            },
+        }
+    }
+    if let [a, b, c] = remaining[..] {
+        if cross(points[a], points[b], points[c]) != 0.0 {
+            triangles.push([points[a], points[b], points[c]]);
+        }
+    }
+
+    triangles
+}
+
+// Twice the signed area of the triangle abc, positive when it turns anticlockwise in
+// y-up coordinates.
+fn cross(a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> f64 {
+    (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+}
+
+// Inside or on the edge of the triangle abc, whichever way it winds.
+fn in_triangle(p: [f64; 2], a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> bool {
+    let (d0, d1, d2) = (cross(a, b, p), cross(b, c, p), cross(c, a, p));
+    let negative = d0 < 0.0 || d1 < 0.0 || d2 < 0.0;
+    let positive = d0 > 0.0 || d1 > 0.0 || d2 > 0.0;
+    !(negative && positive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area(polygon: &[[f64; 2]]) -> f64 {
+        (0..polygon.len()).map(|i| cross([0.0, 0.0], polygon[i], polygon[(i + 1) % polygon.len()])).sum::<f64>().abs() / 2.0
+    }
+
+    // Even-odd test, as the raster and terminal backends fill with.
+    fn contains(polygon: &[[f64; 2]], p: [f64; 2]) -> bool {
+        let mut inside = false;
+        let mut j = polygon.len() - 1;
+        for i in 0..polygon.len() {
+            let (a, b) = (polygon[i], polygon[j]);
+            if (a[1] > p[1]) != (b[1] > p[1]) && p[0] < (b[0] - a[0]) * (p[1] - a[1]) / (b[1] - a[1]) + a[0] {
+                inside = !inside;
+            }
+            j = i;
+        }
+        inside
+    }
+
+    fn assert_covers(polygon: &[[f64; 2]]) {
+        let triangles = triangulate(polygon);
+        let total = triangles.iter().map(|t| area(t)).sum::<f64>();
+        assert!((total - area(polygon)).abs() < 1e-9, "triangles cover {} of {}", total, area(polygon));
+        for [a, b, c] in triangles {
+            let centre = [(a[0] + b[0] + c[0]) / 3.0, (a[1] + b[1] + c[1]) / 3.0];
+            assert!(contains(polygon, centre), "triangle {:?} lies outside the polygon", [a, b, c]);
+        }
+    }
+
+    #[test]
+    fn concave_shapes_stay_inside() {
+        let l_shape = [[0.0, 0.0], [100.0, 0.0], [100.0, 40.0], [40.0, 40.0], [40.0, 100.0], [0.0, 100.0]];
+        assert_covers(&l_shape);
+        let notch = [[0.0, 0.0], [40.0, 0.0], [40.0, 60.0], [60.0, 60.0], [60.0, 0.0], [100.0, 0.0], [100.0, 100.0], [0.0, 100.0]];
+        assert_covers(&notch);
+    }
+
+    #[test]
+    fn either_winding() {
+        let mut l_shape = vec![[0.0, 0.0], [100.0, 0.0], [100.0, 40.0], [40.0, 40.0], [40.0, 100.0], [0.0, 100.0]];
+        l_shape.reverse();
+        assert_covers(&l_shape);
+    }
+
+    #[test]
+    fn straight_runs_and_closing_point() {
+        let square = [[0.0, 0.0], [50.0, 0.0], [100.0, 0.0], [100.0, 100.0], [0.0, 100.0], [0.0, 50.0], [0.0, 0.0]];
+        assert_covers(&square[..6]);
+        assert_eq!(triangulate(&square).len(), triangulate(&square[..6]).len());
+    }
+
+    #[test]
+    fn degenerate_polygons_draw_nothing() {
+        assert!(triangulate(&[[0.0, 0.0], [10.0, 0.0]]).is_empty());
+        assert!(triangulate(&[[0.0, 0.0], [10.0, 0.0], [20.0, 0.0]]).is_empty());
+    }
+}
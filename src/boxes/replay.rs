@@ -0,0 +1,258 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ::image::codecs::gif::{
+    GifEncoder,
+    Repeat
+};
+use ::image::{
+    Delay,
+    Frame,
+    ImageError
+};
+use serde::{
+    Deserialize,
+    Serialize
+};
+
+use super::input::Action;
+use super::player::Movement;
+use super::{
+    Boxes,
+    BoxesError,
+    GameMode
+};
+
+pub const VERSION: u32 = 3;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum InputEvent {
+    Pressed(Action),
+    Released(Action),
+    Waypoint {
+        x: i32,
+        y: i32,
+    },
+    ClearWaypoints,
+    Steer {
+        heading: Option<f64>,
+    },
+}
+
+// Input for one player, numbered from zero. Player one's is stored without a number.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct PlayerInput {
+    #[serde(default, skip_serializing_if = "is_first")]
+    pub player: usize,
+    pub event: InputEvent,
+}
+
+fn is_first(player: &usize) -> bool {
+    *player == 0
+}
+
+fn one_player() -> usize {
+    1
+}
+
+// A recording is a JSON header line followed by one line per simulation tick, holding the
+// game time that tick advanced by in microseconds and the actions that arrived before it.
+// Actions rather than keys are stored so replays don't depend on the players' bindings.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Header {
+    pub version: u32,
+    pub seed: u64,
+    pub mode: GameMode,
+    #[serde(default)]
+    pub movement: Movement,
+    #[serde(default = "one_player")]
+    pub players: usize,
+    #[serde(default)]
+    pub shared_loops: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Tick {
+    pub dt: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub input: Vec<PlayerInput>,
+}
+
+impl Tick {
+    pub fn duration(&self) -> Duration {
+        Duration::from_micros(self.dt)
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Format {
+        line: usize,
+        source: serde_json::Error,
+    },
+    Version(u32),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "{}", e),
+            ReplayError::Format { line, source } => write!(f, "line {}: {}", line, source),
+            ReplayError::Version(v) => write!(f, "unsupported recording version {}", v),
+        }
+    }
+}
+
+impl Error for ReplayError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ReplayError::Io(e) => Some(e),
+            ReplayError::Format { source, .. } => Some(source),
+            ReplayError::Version(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(e: io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+pub struct Recorder {
+    path: PathBuf,
+    out: BufWriter<File>,
+    input: Vec<PlayerInput>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, header: &Header) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut out, header)?;
+        writeln!(out)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            out,
+            input: vec![],
+        })
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn input(&mut self, player: usize, event: InputEvent) {
+        self.input.push(PlayerInput { player, event });
+    }
+
+    pub fn tick(&mut self, dt: Duration) -> io::Result<()> {
+        let tick = Tick {
+            dt: dt.as_micros() as u64,
+            input: std::mem::take(&mut self.input),
+        };
+        serde_json::to_writer(&mut self.out, &tick)?;
+        writeln!(self.out)
+    }
+}
+
+pub struct Recording {
+    pub header: Header,
+    pub ticks: Vec<Tick>,
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> Result<Self, ReplayError> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header: Header = match lines.next() {
+            Some(line) => serde_json::from_str(&line?).map_err(|source| ReplayError::Format { line: 1, source })?,
+            None => return Err(ReplayError::Io(io::ErrorKind::UnexpectedEof.into())),
+        };
+        if header.version != VERSION {
+            return Err(ReplayError::Version(header.version));
+        }
+
+        let mut ticks = vec![];
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            ticks.push(serde_json::from_str(&line).map_err(|source| ReplayError::Format { line: i + 2, source })?);
+        }
+
+        Ok(Self { header, ticks })
+    }
+}
+
+impl Boxes {
+    // Re-simulates a recording without a window and writes every `every`th tick either
+    // as an animated GIF, when `out` ends in .gif, or as numbered PNGs in the `out`
+    // directory.
+    pub fn export(&mut self, inputs: &Path, out: &Path, every: usize) -> Result<(), BoxesError> {
+        let recording = Recording::load(inputs)
+            .map_err(|source| BoxesError::Replay { path: inputs.to_path_buf(), source })?;
+        self.set_mode(recording.header.mode);
+        self.set_movement(recording.header.movement);
+        self.set_players(recording.header.players);
+        self.set_shared_loops(recording.header.shared_loops);
+        self.set_seed(recording.header.seed);
+
+        let save_error = |source: ImageError| BoxesError::Save { path: out.to_path_buf(), source };
+        let gif = out.extension().is_some_and(|e| e.eq_ignore_ascii_case("gif"));
+        let mut encoder = if gif {
+            let mut encoder = GifEncoder::new_with_speed(File::create(out).map_err(|e| save_error(e.into()))?, 10);
+            encoder.set_repeat(Repeat::Infinite).map_err(save_error)?;
+            Some(encoder)
+        } else {
+            fs::create_dir_all(out).map_err(|e| save_error(e.into()))?;
+            None
+        };
+
+        let mut renderer = self.raster_renderer()?;
+        let mut delay = Duration::ZERO;
+        let mut frames = 0;
+        for (i, tick) in recording.ticks.iter().enumerate() {
+            self.replay(tick);
+            delay += tick.duration();
+            if i % every.max(1) != 0 {
+                continue;
+            }
+
+            self.draw(&mut renderer);
+            match encoder.as_mut() {
+                Some(encoder) => {
+                    let delay_ms = delay.as_millis().max(1) as u32;
+                    let frame = Frame::from_parts(renderer.frame().clone(), 0, 0, Delay::from_numer_denom_ms(delay_ms, 1));
+                    encoder.encode_frame(frame).map_err(save_error)?;
+                },
+                None => {
+                    let path = out.join(format!("frame_{:05}.png", frames));
+                    renderer.frame()
+                        .save(&path)
+                        .map_err(|source| BoxesError::Save { path, source })?;
+                },
+            }
+            delay = Duration::ZERO;
+            frames += 1;
+        }
+
+        println!("exported {} frames from {} ticks to {}", frames, recording.ticks.len(), out.display());
+        Ok(())
+    }
+
+    fn replay(&mut self, tick: &Tick) {
+        for input in tick.input.iter() {
+            let player = input.player;
+            if player >= self.player_count() {
+                continue;
+            }
+            self.apply_input(player, input.event);
+        }
+        self.step(tick.duration());
+    }
+}
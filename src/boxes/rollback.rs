@@ -0,0 +1,340 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::{
+    Deserialize,
+    Serialize
+};
+
+use super::enemy::Enemy;
+use super::input::Action;
+use super::level::Level;
+use super::net::{
+    Conditions,
+    Link,
+    TICK
+};
+use super::replay::InputEvent;
+use super::territory::Territory;
+use super::{
+    Boxes,
+    BoxesError,
+    Capture,
+    GameState,
+    PlayerSlot
+};
+
+// Local input is held back this many frames before it is simulated, which gives it time to
+// reach the peer and saves most rollbacks.
+const INPUT_DELAY: u64 = 3;
+// How far the game may run ahead of the peer's confirmed input before it waits for them.
+const MAX_ROLLBACK: u64 = 30;
+// How long the loopback harness keeps sending once it is done, so the peer can finish too.
+const LINGER: Duration = Duration::from_secs(1);
+// How long the harness waits for the peer's input to move on before giving up on them.
+const PEER_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Everything the simulation changes from tick to tick, so the game can be put back to how
+// it was and played forward again.
+#[derive(Deserialize, Serialize)]
+pub struct Snapshot {
+    players: Vec<PlayerSlot>,
+    enemies: Vec<Enemy>,
+    level: Level,
+    rng: ChaCha12Rng,
+    clock: Duration,
+    paused: bool,
+    difficulty: u32,
+    territory: Territory,
+    captures: Vec<Capture>,
+    state: GameState,
+}
+
+// Every message carries all of the sender's input the receiver hasn't acknowledged yet, one
+// entry per frame starting at `from`, so lost messages don't need to be asked for again.
+#[derive(Debug, Deserialize, Serialize)]
+struct PeerMessage {
+    seed: u64,
+    // The number of frames of the receiver's input the sender has.
+    ack: u64,
+    from: u64,
+    frames: Vec<Vec<InputEvent>>,
+}
+
+// One side of a two player game where both peers run the whole simulation. The peer's input
+// is predicted to be unchanged until it arrives, and when it turns out otherwise the game is
+// rolled back to that frame and simulated again with what they actually did.
+pub struct Peer {
+    link: Link,
+    addr: SocketAddr,
+    player: usize,
+    seed: u64,
+    started: bool,
+    frame: u64,
+    until: Option<u64>,
+    accumulated: Duration,
+    pending: Vec<InputEvent>,
+    local: BTreeMap<u64, Vec<InputEvent>>,
+    acked: u64,
+    remote: BTreeMap<u64, Vec<InputEvent>>,
+    confirmed: u64,
+    snapshots: VecDeque<(u64, Snapshot)>,
+    rollbacks: u64,
+}
+
+impl Peer {
+    // Both peers list each other's address, and whichever listens on the lower port is
+    // player one and picks the seed.
+    fn connect(socket: UdpSocket, peer: &str, seed: u64, conditions: Conditions) -> std::io::Result<Self> {
+        let link = Link::on(socket, conditions)?;
+        let addr = peer.to_socket_addrs()?
+            .next()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no address found"))?;
+        let local = link.local_addr()?;
+        let player = if (local.port(), local.ip()) < (addr.port(), addr.ip()) { 0 } else { 1 };
+        Ok(Self {
+            link,
+            addr,
+            player,
+            seed,
+            started: false,
+            frame: 0,
+            until: None,
+            accumulated: Duration::ZERO,
+            pending: vec![],
+            local: BTreeMap::new(),
+            acked: 0,
+            remote: BTreeMap::new(),
+            confirmed: 0,
+            snapshots: VecDeque::new(),
+            rollbacks: 0,
+        })
+    }
+
+    // Local input is played a few frames from now, on both sides.
+    pub(super) fn queue(&mut self, event: InputEvent) {
+        self.pending.push(event);
+    }
+
+    // Runs as many frames as `dt` covers, as far as the peer's input allows.
+    pub(super) fn advance(&mut self, boxes: &mut Boxes, dt: Duration) {
+        self.receive(boxes);
+        if self.started {
+            self.accumulated += dt;
+            while self.accumulated >= TICK {
+                let waiting = self.frame >= self.confirmed + MAX_ROLLBACK;
+                if waiting || self.until.is_some_and(|until| self.frame >= until) {
+                    self.accumulated = TICK;
+                    break;
+                }
+
+                self.accumulated -= TICK;
+                self.local.insert(self.frame + INPUT_DELAY, mem::take(&mut self.pending));
+                self.simulate(boxes);
+            }
+        }
+        self.send();
+        self.link.flush();
+    }
+
+    fn receive(&mut self, boxes: &mut Boxes) {
+        let mut rollback = None;
+        while let Some((from, message)) = self.link.recv::<PeerMessage>() {
+            if from != self.addr {
+                continue;
+            }
+            if !self.started {
+                if self.player == 1 {
+                    self.seed = message.seed;
+                }
+                boxes.set_seed(self.seed);
+                self.started = true;
+                println!("playing {} as player {}", self.addr, self.player + 1);
+            }
+
+            self.acked = self.acked.max(message.ack);
+            for (i, events) in message.frames.into_iter().enumerate() {
+                let frame = message.from + i as u64;
+                if frame != self.confirmed {
+                    continue;
+                }
+                // Nothing new was predicted, so any input for a frame already simulated
+                // means it has to be simulated again.
+                if frame < self.frame && !events.is_empty() {
+                    rollback.get_or_insert(frame);
+                }
+                self.remote.insert(frame, events);
+                self.confirmed += 1;
+            }
+        }
+
+        if let Some(frame) = rollback {
+            self.rollback(boxes, frame);
+        }
+
+        // Only frames the peer's input hasn't been confirmed for can need rolling back.
+        while self.snapshots.front().is_some_and(|(frame, _)| *frame < self.confirmed) {
+            self.snapshots.pop_front();
+        }
+        let oldest = self.snapshots.front().map_or(self.frame, |(frame, _)| *frame);
+        self.remote = self.remote.split_off(&oldest);
+        self.local = self.local.split_off(&oldest.min(self.acked));
+    }
+
+    fn rollback(&mut self, boxes: &mut Boxes, frame: u64) {
+        let index = match self.snapshots.iter().position(|(f, _)| *f == frame) {
+            Some(i) => i,
+            None => return,
+        };
+        let target = self.frame;
+        boxes.restore(&self.snapshots[index].1);
+        self.snapshots.truncate(index);
+        self.frame = frame;
+        while self.frame < target {
+            self.simulate(boxes);
+        }
+        self.rollbacks += 1;
+    }
+
+    // Plays one frame, with both players' input applied in the same order on either side.
+    fn simulate(&mut self, boxes: &mut Boxes) {
+        self.snapshots.push_back((self.frame, boxes.snapshot()));
+        for player in 0..2 {
+            let events = if player == self.player {
+                self.local.get(&self.frame)
+            } else {
+                self.remote.get(&self.frame)
+            };
+            for event in events.into_iter().flatten() {
+                boxes.apply_input(player, *event);
+            }
+        }
+        boxes.step(TICK);
+        self.frame += 1;
+    }
+
+    fn send(&mut self) {
+        let message = PeerMessage {
+            seed: self.seed,
+            ack: self.confirmed,
+            from: self.acked,
+            frames: (self.acked..self.frame + INPUT_DELAY)
+                .map(|frame| self.local.get(&frame).cloned().unwrap_or_default())
+                .collect(),
+        };
+        self.link.send(self.addr, &message);
+    }
+}
+
+impl Boxes {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            players: self.players.clone(),
+            enemies: self.enemies.clone(),
+            level: self.level.clone(),
+            rng: self.rng.clone(),
+            clock: self.clock,
+            paused: self.paused,
+            difficulty: self.difficulty,
+            territory: self.territory.clone(),
+            captures: self.captures.clone(),
+            state: self.state,
+        }
+    }
+
+    // Key bindings aren't part of the game, so they stay as they are.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        let bindings = self.players.iter().map(|slot| slot.bindings.clone()).collect::<Vec<_>>();
+        self.players = snapshot.players.clone();
+        for (slot, bindings) in self.players.iter_mut().zip(bindings) {
+            slot.bindings = bindings;
+        }
+        self.enemies = snapshot.enemies.clone();
+        self.level = snapshot.level.clone();
+        self.rng = snapshot.rng.clone();
+        self.clock = snapshot.clock;
+        self.paused = snapshot.paused;
+        self.difficulty = snapshot.difficulty;
+        self.territory = snapshot.territory.clone();
+        self.captures = snapshot.captures.clone();
+        self.state = snapshot.state;
+    }
+
+    // A digest of what the game looks like, which is the same on both peers while they
+    // agree on what happened.
+    pub fn checksum(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        serde_json::to_vec(&self.view()).unwrap_or_default().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // Plays a two player game against the peer listening on `peer`, with both sides running
+    // the simulation. Recordings aren't made, since rolling back replays input.
+    pub fn connect_peer(&mut self, listen: &str, peer: &str, conditions: Conditions) -> Result<(), BoxesError> {
+        let socket = UdpSocket::bind(listen)
+            .map_err(|source| BoxesError::Network { addr: listen.to_string(), source })?;
+        self.connect_peer_on(socket, peer, conditions)
+    }
+
+    // As `connect_peer`, on a socket that is already bound, such as one on a port the
+    // system picked whose address has been passed to the peer.
+    pub fn connect_peer_on(&mut self, socket: UdpSocket, peer: &str, conditions: Conditions) -> Result<(), BoxesError> {
+        let peer = Peer::connect(socket, peer, self.seed, conditions)
+            .map_err(|source| BoxesError::Network { addr: peer.to_string(), source })?;
+        self.set_players(2);
+        self.recorder = None;
+        self.peer = Some(peer);
+        Ok(())
+    }
+
+    // Plays `frames` frames against the peer with random held directions and returns the
+    // checksum they ended on and how many times the game was rolled back, or `None` if the
+    // peer stopped playing first.
+    pub fn play_loopback(&mut self, frames: u64) -> Option<(u64, u64)> {
+        let player = {
+            let peer = self.peer.as_mut()?;
+            peer.until = Some(frames);
+            peer.player
+        };
+        let directions = [Action::MoveUp, Action::MoveDown, Action::MoveLeft, Action::MoveRight];
+        let mut rng = StdRng::seed_from_u64(player as u64);
+        let mut held = None;
+        let mut last = Instant::now();
+        let mut done: Option<Instant> = None;
+        let mut progress = (0, Instant::now());
+        while done.is_none_or(|t| t.elapsed() < LINGER) {
+            if done.is_none() && rng.gen_ratio(1, 40) {
+                if let Some(action) = held.take() {
+                    self.release_action(player, action);
+                }
+                let action = directions[rng.gen_range(0..directions.len())];
+                self.press_action(player, action);
+                held = Some(action);
+            }
+
+            let now = Instant::now();
+            self.step(now - last);
+            last = now;
+            let confirmed = self.peer.as_ref().map_or(0, |p| p.confirmed);
+            if confirmed != progress.0 {
+                progress = (confirmed, Instant::now());
+            } else if done.is_none() && progress.1.elapsed() >= PEER_TIMEOUT {
+                return None;
+            }
+            if done.is_none() && self.peer.as_ref().is_some_and(|p| p.frame >= frames && p.confirmed >= frames) {
+                done = Some(Instant::now());
+            }
+            thread::sleep(TICK / 2);
+        }
+
+        self.peer.as_ref().map(|p| (self.checksum(), p.rollbacks))
+    }
+}
@@ -0,0 +1,283 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use piston::{
+    Position,
+    Size
+};
+use serde::{
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer
+};
+
+use super::rollback::Snapshot;
+use super::{
+    Boxes,
+    BoxesError,
+    GameMode
+};
+
+// Bumped whenever the saved state changes shape, since old saves can't be loaded into it.
+pub const VERSION: u32 = 1;
+
+#[derive(Deserialize, Serialize)]
+#[serde(remote = "Position")]
+pub struct PositionDef {
+    x: i32,
+    y: i32,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(remote = "Size")]
+pub struct SizeDef {
+    width: f64,
+    height: f64,
+}
+
+// For lists of positions, which `PositionDef` can't be applied to directly. Each is
+// written as an [x, y] pair.
+pub mod positions {
+    use super::*;
+
+    pub fn serialize<'a, S, C>(positions: &'a C, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        &'a C: IntoIterator<Item = &'a Position>,
+    {
+        serializer.collect_seq(positions.into_iter().map(|p| [p.x, p.y]))
+    }
+
+    pub fn deserialize<'de, D, C>(deserializer: D) -> Result<C, D::Error>
+    where
+        D: Deserializer<'de>,
+        C: FromIterator<Position>,
+    {
+        let pairs = Vec::<[i32; 2]>::deserialize(deserializer)?;
+        Ok(pairs.into_iter().map(Position::from).collect())
+    }
+}
+
+// A save holds the settings the game was started with alongside its state, which is what
+// rolling back restores.
+#[derive(Deserialize, Serialize)]
+struct SaveFile {
+    version: u32,
+    seed: u64,
+    mode: GameMode,
+    #[serde(default)]
+    shared_loops: bool,
+    state: Snapshot,
+}
+
+// Just the version, so saves from other versions are reported as such rather than as
+// whatever field no longer matches.
+#[derive(Deserialize)]
+struct SaveVersion {
+    version: u32,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    Format(serde_json::Error),
+    Version(u32),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::Io(e) => write!(f, "{}", e),
+            SaveError::Format(e) => write!(f, "{}", e),
+            SaveError::Version(v) => write!(f, "unsupported save version {}", v),
+        }
+    }
+}
+
+impl Error for SaveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SaveError::Io(e) => Some(e),
+            SaveError::Format(e) => Some(e),
+            SaveError::Version(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(e: io::Error) -> Self {
+        SaveError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SaveError {
+    fn from(e: serde_json::Error) -> Self {
+        SaveError::Format(e)
+    }
+}
+
+impl Boxes {
+    pub fn save_game(&self, path: &Path) -> Result<(), BoxesError> {
+        let file = SaveFile {
+            version: VERSION,
+            seed: self.seed,
+            mode: self.mode,
+            shared_loops: self.shared_loops,
+            state: self.snapshot(),
+        };
+        serde_json::to_vec(&file)
+            .map_err(SaveError::from)
+            .and_then(|json| fs::write(path, json).map_err(SaveError::from))
+            .map_err(|source| BoxesError::SaveGame { path: path.to_path_buf(), source })
+    }
+
+    // Picks up exactly where the save left off. Key bindings and sprites stay as they are.
+    pub fn load_game(&mut self, path: &Path) -> Result<(), BoxesError> {
+        let file = Self::read_save(path).map_err(|source| BoxesError::SaveGame { path: path.to_path_buf(), source })?;
+        self.seed = file.seed;
+        self.mode = file.mode;
+        self.shared_loops = file.shared_loops;
+        self.restore(&file.state);
+        self.assign_presets();
+        self.reload_sheets();
+        if self.recorder.take().is_some() {
+            println!("loaded a save, recording stopped");
+        }
+        Ok(())
+    }
+
+    fn read_save(path: &Path) -> Result<SaveFile, SaveError> {
+        let json = fs::read(path)?;
+        let version = serde_json::from_slice::<SaveVersion>(&json)?.version;
+        if version != VERSION {
+            return Err(SaveError::Version(version));
+        }
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    // Saves and loads from the keyboard report how it went on stdout, like other settings
+    // files. A networked game isn't this copy's to save or replace.
+    pub(super) fn quicksave(&mut self) {
+        if self.remote.is_some() || self.peer.is_some() {
+            println!("networked games can't be saved");
+            return;
+        }
+        match self.save_game(&self.save_path) {
+            Ok(()) => println!("saved to {}", self.save_path.display()),
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    pub(super) fn quickload(&mut self) {
+        if self.remote.is_some() || self.peer.is_some() {
+            println!("networked games can't be loaded");
+            return;
+        }
+        let path = self.save_path.clone();
+        match self.load_game(&path) {
+            Ok(()) => println!("loaded {}", path.display()),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::boxes::input::Action;
+    use crate::boxes::{
+        set_debug,
+        Conditions
+    };
+
+    const TICK: Duration = Duration::from_micros(4167);
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("boxes-save-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // A game part way through, with a trail down and the enemies on the move.
+    fn played() -> Boxes {
+        set_debug(false);
+        let mut boxes = Boxes::new(800, 800, 3, 10);
+        boxes.set_seed(11);
+        boxes.press_action(0, Action::MoveRight);
+        for _ in 0..40 {
+            boxes.step(TICK);
+        }
+        boxes.press_action(0, Action::MoveDown);
+        for _ in 0..40 {
+            boxes.step(TICK);
+        }
+        boxes
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let dir = scratch("round-trip");
+        let path = dir.join("save.json");
+        let mut saved = played();
+        saved.save_game(&path).unwrap();
+
+        let mut loaded = Boxes::new(800, 800, 1, 10);
+        loaded.set_seed(99);
+        loaded.load_game(&path).unwrap();
+        assert_eq!(loaded.checksum(), saved.checksum());
+        assert_eq!(loaded.seed, 11);
+
+        // Both carry on the same way, random enemy moves included.
+        for _ in 0..200 {
+            saved.step(TICK);
+            loaded.step(TICK);
+        }
+        assert_eq!(loaded.checksum(), saved.checksum());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let dir = scratch("version");
+        let path = dir.join("save.json");
+        played().save_game(&path).unwrap();
+        let json = fs::read_to_string(&path).unwrap().replacen("\"version\":1", "\"version\":2", 1);
+        fs::write(&path, json).unwrap();
+
+        let mut boxes = Boxes::new(800, 800, 1, 10);
+        let before = boxes.checksum();
+        match boxes.load_game(&path) {
+            Err(BoxesError::SaveGame { source: SaveError::Version(2), .. }) => {},
+            other => panic!("expected a version error, got {:?}", other),
+        }
+        assert_eq!(boxes.checksum(), before);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn networked_games_arent_saved_or_loaded() {
+        let dir = scratch("networked");
+        let path = dir.join("save.json");
+        let mut boxes = played();
+        boxes.set_save_path(path.clone());
+        boxes.connect_peer("127.0.0.1:0", "127.0.0.1:9", Conditions::default()).unwrap();
+        boxes.quicksave();
+        assert!(!path.exists());
+
+        // A single player save, which would take the second player away if it were loaded.
+        played().save_game(&path).unwrap();
+        let before = boxes.checksum();
+        boxes.quickload();
+        assert_eq!(boxes.checksum(), before);
+        assert_eq!(boxes.player_count(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,309 @@
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tungstenite::protocol::WebSocketConfig;
+use tungstenite::{
+    Message,
+    WebSocket
+};
+
+use super::enemy::EnemyState;
+use super::{
+    Boxes,
+    BoxesError,
+    GameMode
+};
+
+// Spectators are sent a frame about 30 times a second, whatever the game's tick rate.
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+// Connections beyond this many still shaking hands are turned away.
+const MAX_HANDSHAKES: usize = 16;
+const MAX_REQUEST: usize = 4096;
+// A spectator that falls this far behind is dropped rather than buffered for.
+const MAX_BACKLOG: usize = 1 << 20;
+// Browsers that open the address directly are given the viewer, which connects back for
+// the frames.
+const VIEWER: &str = include_str!("../../viewer/spectator.html");
+
+#[derive(Serialize)]
+struct PlayerFrame {
+    position: [f64; 2],
+    facing: f64,
+    moving: bool,
+    alive: bool,
+    stunned: bool,
+    lives: u32,
+    caught: u32,
+    color: [f32; 4],
+    // Each segment as from x, from y, to x, to y.
+    trail: Vec<[f64; 4]>,
+}
+
+#[derive(Serialize)]
+struct EnemyFrame {
+    position: [f64; 2],
+    state: EnemyState,
+    aggressive: bool,
+}
+
+// What spectators are sent every frame, as JSON text messages.
+#[derive(Serialize)]
+struct Frame {
+    // Game time in milliseconds.
+    clock: u64,
+    arena: [f64; 2],
+    mode: GameMode,
+    level: u32,
+    paused: bool,
+    obstacles: Vec<[f64; 4]>,
+    // The claimed percentage and the target, in territory mode.
+    territory: Option<[f64; 2]>,
+    players: Vec<PlayerFrame>,
+    enemies: Vec<EnemyFrame>,
+}
+
+type Handshake = (SocketAddr, Result<Option<WebSocket<TcpStream>>, Box<dyn Error + Send + Sync>>);
+
+// Handshakes are done on threads of their own, so a connection that is slow to say what
+// it wants can't hold up the game.
+pub struct Spectators {
+    listener: TcpListener,
+    sockets: Vec<WebSocket<TcpStream>>,
+    handshakes: usize,
+    opened: (Sender<Handshake>, Receiver<Handshake>),
+    last_sent: Option<Instant>,
+}
+
+impl Spectators {
+    fn bind(addr: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            sockets: vec![],
+            handshakes: 0,
+            opened: mpsc::channel(),
+            last_sent: None,
+        })
+    }
+
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((_, from)) if self.handshakes >= MAX_HANDSHAKES => {
+                    println!("spectator {} turned away, too many connecting", from);
+                },
+                Ok((stream, from)) => {
+                    let opened = self.opened.0.clone();
+                    self.handshakes += 1;
+                    thread::spawn(move || {
+                        let _ = opened.send((from, Self::open(stream)));
+                    });
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    println!("failed to accept spectator: {}", e);
+                    break;
+                },
+            }
+        }
+
+        while let Ok((from, result)) = self.opened.1.try_recv() {
+            self.handshakes -= 1;
+            match result {
+                Ok(Some(socket)) => {
+                    println!("spectator {} connected", from);
+                    self.sockets.push(socket);
+                },
+                Ok(None) => {},
+                Err(e) => println!("spectator {} failed to connect: {}", from, e),
+            }
+        }
+    }
+
+    // Upgrades a WebSocket request, or answers anything else with the viewer page.
+    fn open(mut stream: TcpStream) -> Result<Option<WebSocket<TcpStream>>, Box<dyn Error + Send + Sync>> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let start = Instant::now();
+        let mut request = vec![0; MAX_REQUEST];
+        let len = loop {
+            // The read timeout shows up as either of these, depending on the platform.
+            let len = match stream.peek(&mut request) {
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    return Err("request timed out".into());
+                },
+                result => result?,
+            };
+            if len == 0 || len == MAX_REQUEST || request[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+                break len;
+            }
+            if start.elapsed() >= HANDSHAKE_TIMEOUT {
+                return Err("request timed out".into());
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        let header = String::from_utf8_lossy(&request[..len]).to_ascii_lowercase();
+        if !header.contains("upgrade: websocket") {
+            stream.read_exact(&mut request[..len])?;
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                VIEWER.len(),
+                VIEWER
+            )?;
+            return Ok(None);
+        }
+
+        let config = WebSocketConfig {
+            max_write_buffer_size: MAX_BACKLOG,
+            ..WebSocketConfig::default()
+        };
+        let socket = tungstenite::accept_with_config(stream, Some(config)).map_err(|e| e.to_string())?;
+        socket.get_ref().set_nonblocking(true)?;
+        Ok(Some(socket))
+    }
+
+    // Sends `frame` to every spectator, dropping those that have gone away.
+    fn send(&mut self, frame: &str) {
+        self.sockets.retain_mut(|socket| {
+            // Spectators don't send anything, but reading answers pings and notices closes.
+            loop {
+                match socket.read() {
+                    Ok(_) => continue,
+                    Err(tungstenite::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(_) => return false,
+                }
+            }
+            match socket.send(Message::text(frame)) {
+                Ok(()) => true,
+                Err(tungstenite::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => true,
+                Err(e) => {
+                    println!("spectator disconnected: {}", e);
+                    false
+                },
+            }
+        });
+    }
+}
+
+impl Boxes {
+    // Streams the game as JSON over a WebSocket on `addr` for anyone who wants to watch.
+    pub fn spectate(&mut self, addr: &str) -> Result<(), BoxesError> {
+        let spectators = Spectators::bind(addr)
+            .map_err(|source| BoxesError::Network { addr: addr.to_string(), source })?;
+        println!("spectators can watch on http://{}", addr);
+        self.spectators = Some(spectators);
+        Ok(())
+    }
+
+    // Called by front ends once a frame, after the game has been stepped.
+    pub(super) fn update_spectators(&mut self) {
+        let mut spectators = match self.spectators.take() {
+            Some(s) => s,
+            None => return,
+        };
+        spectators.accept();
+        let due = spectators.last_sent.is_none_or(|t| t.elapsed() >= FRAME_INTERVAL);
+        // A client's own copy of the game isn't what's being played.
+        if due && !spectators.sockets.is_empty() && self.remote.is_none() {
+            match serde_json::to_string(&self.spectator_frame()) {
+                Ok(frame) => spectators.send(&frame),
+                Err(e) => println!("failed to encode spectator frame: {}", e),
+            }
+            spectators.last_sent = Some(Instant::now());
+        }
+        self.spectators = Some(spectators);
+    }
+
+    fn spectator_frame(&self) -> Frame {
+        let point = |p: piston::Position| [p.x as f64, p.y as f64];
+        Frame {
+            clock: self.clock.as_millis() as u64,
+            arena: [self.arena_size.width, self.arena_size.height],
+            mode: self.mode,
+            level: self.difficulty,
+            paused: self.paused,
+            obstacles: self.level.obstacles.iter()
+                .map(|o| [o.x as f64, o.y as f64, o.width as f64, o.height as f64])
+                .collect(),
+            territory: match self.mode {
+                GameMode::Territory(target) => Some([self.territory.percentage(), target]),
+                GameMode::Capture | GameMode::Versus => None,
+            },
+            players: self.players.iter().map(|slot| PlayerFrame {
+                position: point(slot.player.get_cur_position()),
+                facing: slot.player.get_facing(),
+                moving: slot.player.is_moving(),
+                alive: slot.is_alive(),
+                stunned: !slot.stunned.is_zero(),
+                lives: slot.lives,
+                caught: slot.caught,
+                color: slot.trail,
+                trail: slot.lines.iter()
+                    .map(|l| [l.from.x as f64, l.from.y as f64, l.to.x as f64, l.to.y as f64])
+                    .collect(),
+            }).collect(),
+            enemies: self.enemies.iter().map(|e| EnemyFrame {
+                position: point(e.get_position()),
+                state: e.get_state(),
+                aggressive: e.is_aggressive(),
+            }).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listening() -> (Spectators, SocketAddr) {
+        let spectators = Spectators::bind("127.0.0.1:0").unwrap();
+        let addr = spectators.listener.local_addr().unwrap();
+        (spectators, addr)
+    }
+
+    // Accepts until `done` or a second has passed, returning how long the longest call took.
+    fn accept_until(spectators: &mut Spectators, done: impl Fn(&Spectators) -> bool) -> Duration {
+        let start = Instant::now();
+        let mut longest = Duration::ZERO;
+        while !done(spectators) && start.elapsed() < Duration::from_secs(1) {
+            let call = Instant::now();
+            spectators.accept();
+            longest = longest.max(call.elapsed());
+            thread::sleep(Duration::from_millis(5));
+        }
+        longest
+    }
+
+    #[test]
+    fn silent_connections_dont_block() {
+        let (mut spectators, addr) = listening();
+        let _silent = (0..4).map(|_| TcpStream::connect(addr).unwrap()).collect::<Vec<_>>();
+        let longest = accept_until(&mut spectators, |s| s.handshakes == 4);
+        assert_eq!(spectators.handshakes, 4);
+        assert!(longest < Duration::from_millis(100), "accepting took {:?}", longest);
+    }
+
+    #[test]
+    fn websocket_clients_are_handed_back() {
+        let (mut spectators, addr) = listening();
+        let client = thread::spawn(move || {
+            let stream = TcpStream::connect(addr).unwrap();
+            let (mut socket, _) = tungstenite::client(format!("ws://{}/", addr), stream).unwrap();
+            socket.read().unwrap()
+        });
+        accept_until(&mut spectators, |s| !s.sockets.is_empty());
+        assert_eq!(spectators.sockets.len(), 1);
+        assert_eq!(spectators.handshakes, 0);
+
+        spectators.send("{}");
+        assert_eq!(client.join().unwrap(), Message::text("{}"));
+    }
+}
@@ -0,0 +1,276 @@
+use piston::{
+    Position,
+    Size
+};
+use serde::{
+    Deserialize,
+    Serialize
+};
+
+use super::save::SizeDef;
+
+const CELL_SIZE: i32 = 8;
+const BORDER_WIDTH: i32 = 16;
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Territory {
+    #[serde(with = "SizeDef")]
+    arena: Size,
+    columns: i32,
+    rows: i32,
+    cells: Vec<bool>,
+    claimed: usize,
+}
+
+impl Territory {
+    pub fn new(arena: Size) -> Self {
+        let columns = arena.width as i32 / CELL_SIZE;
+        let rows = arena.height as i32 / CELL_SIZE;
+        Self {
+            arena,
+            columns,
+            rows,
+            cells: vec![false; (columns * rows) as usize],
+            claimed: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.cells.iter_mut().for_each(|c| *c = false);
+        self.claimed = 0;
+    }
+
+    // Of the cells inside the border, which is the only part of the arena that can be
+    // claimed towards the target.
+    pub fn percentage(&self) -> f64 {
+        let interior = self.interior_cells();
+        if interior == 0 {
+            return 0.0;
+        }
+
+        self.claimed as f64 * 100.0 / interior as f64
+    }
+
+    fn interior_cells(&self) -> usize {
+        let columns = (0..self.columns).filter(|col| !in_border(*col, self.arena.width)).count();
+        let rows = (0..self.rows).filter(|row| !in_border(*row, self.arena.height)).count();
+        columns * rows
+    }
+
+    fn is_border(&self, col: i32, row: i32) -> bool {
+        in_border(col, self.arena.width) || in_border(row, self.arena.height)
+    }
+
+    fn index(&self, col: i32, row: i32) -> Option<usize> {
+        if col < 0 || row < 0 || col >= self.columns || row >= self.rows {
+            return None;
+        }
+
+        Some((row * self.columns + col) as usize)
+    }
+
+    pub fn is_claimed(&self, p: Position) -> bool {
+        match self.index(p.x.div_euclid(CELL_SIZE), p.y.div_euclid(CELL_SIZE)) {
+            Some(i) => self.cells[i],
+            None => false,
+        }
+    }
+
+    // The arena border is always safe, but is not counted towards the claimed percentage.
+    pub fn is_safe(&self, p: Position) -> bool {
+        p.x <= BORDER_WIDTH || p.y <= BORDER_WIDTH ||
+            p.x >= self.arena.width as i32 - BORDER_WIDTH ||
+            p.y >= self.arena.height as i32 - BORDER_WIDTH ||
+            self.is_claimed(p)
+    }
+
+    // Claims the trail a player drew from safe ground back to safe ground, along with
+    // every open area beside it but the largest, which is left to play on. The claimed
+    // cells and the border close the areas off, so they follow the edge of the safe ground
+    // rather than cutting straight back to where the trail began. Returns the newly claimed
+    // cells as runs.
+    pub fn enclose(&mut self, trail: &[Position]) -> Vec<[f64; 4]> {
+        let before = self.cells.clone();
+        let mut marked = vec![];
+        for pair in trail.windows(2) {
+            let (from, to) = (pair[0], pair[1]);
+            let steps = (to.x - from.x).abs().max((to.y - from.y).abs()).max(1);
+            for step in 0..=steps {
+                let x = from.x + (to.x - from.x) * step / steps;
+                let y = from.y + (to.y - from.y) * step / steps;
+                let cell = (x.div_euclid(CELL_SIZE), y.div_euclid(CELL_SIZE));
+                if self.mark(cell.0, cell.1) {
+                    marked.push(cell);
+                }
+            }
+        }
+
+        let mut seen = vec![false; self.cells.len()];
+        let mut areas: Vec<Vec<(i32, i32)>> = vec![];
+        for (col, row) in marked {
+            for next in neighbours(col, row) {
+                if let Some(area) = self.flood(next, &mut seen) {
+                    areas.push(area);
+                }
+            }
+        }
+        if let Some(largest) = (0..areas.len()).max_by_key(|a| areas[*a].len()) {
+            areas.swap_remove(largest);
+        }
+        for (col, row) in areas.into_iter().flatten() {
+            self.mark(col, row);
+        }
+
+        self.runs_where(|i| self.cells[i] && !before[i])
+    }
+
+    // Returns whether the cell was newly claimed.
+    fn mark(&mut self, col: i32, row: i32) -> bool {
+        match self.index(col, row) {
+            Some(i) if !self.cells[i] => {
+                self.cells[i] = true;
+                if !self.is_border(col, row) {
+                    self.claimed += 1;
+                }
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn is_open(&self, col: i32, row: i32) -> bool {
+        self.index(col, row).is_some_and(|i| !self.cells[i]) && !self.is_border(col, row)
+    }
+
+    // The open cells joined to `start`, unless it is closed or has already been visited.
+    fn flood(&self, start: (i32, i32), seen: &mut [bool]) -> Option<Vec<(i32, i32)>> {
+        let i = self.index(start.0, start.1)?;
+        if seen[i] || !self.is_open(start.0, start.1) {
+            return None;
+        }
+
+        seen[i] = true;
+        let mut area = vec![];
+        let mut pending = vec![start];
+        while let Some((col, row)) = pending.pop() {
+            area.push((col, row));
+            for (c, r) in neighbours(col, row) {
+                if let Some(i) = self.index(c, r).filter(|i| !seen[*i] && self.is_open(c, r)) {
+                    seen[i] = true;
+                    pending.push((c, r));
+                }
+            }
+        }
+
+        Some(area)
+    }
+
+    // Claimed cells merged into horizontal runs, as [x, y, width, height] rectangles.
+    pub fn runs(&self) -> Vec<[f64; 4]> {
+        self.runs_where(|i| self.cells[i])
+    }
+
+    fn runs_where(&self, claimed: impl Fn(usize) -> bool) -> Vec<[f64; 4]> {
+        let mut runs = vec![];
+        for row in 0..self.rows {
+            let mut start: Option<i32> = None;
+            for col in 0..=self.columns {
+                let claimed = self.index(col, row).is_some_and(&claimed);
+                match (claimed, start) {
+                    (true, None) => start = Some(col),
+                    (false, Some(s)) => {
+                        runs.push([
+                            (s * CELL_SIZE) as f64,
+                            (row * CELL_SIZE) as f64,
+                            ((col - s) * CELL_SIZE) as f64,
+                            CELL_SIZE as f64
+                        ]);
+                        start = None;
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        runs
+    }
+}
+
+fn neighbours(col: i32, row: i32) -> [(i32, i32); 4] {
+    [(col - 1, row), (col + 1, row), (col, row - 1), (col, row + 1)]
+}
+
+// Whether the cell at `index` along an axis of `size` has its centre in the border.
+fn in_border(index: i32, size: f64) -> bool {
+    let centre = index * CELL_SIZE + CELL_SIZE / 2;
+    centre <= BORDER_WIDTH || centre >= size as i32 - BORDER_WIDTH
+}
+
+pub fn contains(polygon: &[Position], point: (f64, f64)) -> bool {
+    if polygon.is_empty() {
+        return false;
+    }
+
+    let (x, y) = point;
+    let mut inside = false;
+    let mut j = polygon.len() - 1;
+    for i in 0..polygon.len() {
+        let (xi, yi) = (polygon[i].x as f64, polygon[i].y as f64);
+        let (xj, yj) = (polygon[j].x as f64, polygon[j].y as f64);
+        if ((yi > y) != (yj > y)) && (x < (xj - xi) * (y - yi) / (yj - yi) + xi) {
+            inside = !inside;
+        }
+        j = i;
+    }
+
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trail(points: &[(i32, i32)]) -> Vec<Position> {
+        points.iter().map(|(x, y)| Position { x: *x, y: *y }).collect()
+    }
+
+    #[test]
+    fn percentage_of_known_rectangle() {
+        let mut territory = Territory::new(Size::from([800, 800]));
+        // The trail runs through columns 2 to 37 and rows 12 to 37, and shuts off everything
+        // between it and the left border. The 96 columns and rows from 2 to 97 are inside
+        // the border.
+        territory.enclose(&trail(&[(16, 100), (300, 100), (300, 300), (16, 300)]));
+        assert!((territory.percentage() - (36.0 * 26.0) * 100.0 / (96.0 * 96.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn smaller_side_of_a_cut_is_claimed() {
+        let mut territory = Territory::new(Size::from([800, 800]));
+        let claimed = territory.enclose(&trail(&[(16, 200), (784, 200)]));
+        assert!(!claimed.is_empty());
+        assert!(territory.is_claimed(Position { x: 400, y: 100 }));
+        assert!(territory.is_claimed(Position { x: 400, y: 200 }));
+        assert!(!territory.is_claimed(Position { x: 400, y: 600 }));
+    }
+
+    #[test]
+    fn enclosure_follows_claimed_edge() {
+        let mut territory = Territory::new(Size::from([800, 800]));
+        territory.enclose(&trail(&[(16, 100), (300, 100), (300, 300), (16, 300)]));
+        // Closing straight back to where this trail began would claim the triangle to the
+        // right of it, rather than the ground below the first claim.
+        territory.enclose(&trail(&[(200, 300), (200, 600), (784, 600)]));
+        assert!(territory.is_claimed(Position { x: 100, y: 500 }));
+        assert!(territory.is_claimed(Position { x: 400, y: 700 }));
+        assert!(!territory.is_claimed(Position { x: 400, y: 500 }));
+    }
+
+    #[test]
+    fn border_is_not_counted() {
+        let mut territory = Territory::new(Size::from([800, 800]));
+        territory.enclose(&trail(&[(400, 0), (400, 8)]));
+        assert!(territory.is_claimed(Position { x: 400, y: 4 }));
+        assert_eq!(territory.percentage(), 0.0);
+    }
+}
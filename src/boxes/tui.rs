@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+use std::io::{self, stdout};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use piston::Position;
+use piston_window::Key;
+use ratatui::DefaultTerminal;
+use ratatui::buffer::Buffer;
+use ratatui::crossterm::event::{
+    self,
+    DisableMouseCapture,
+    EnableMouseCapture,
+    Event,
+    KeyCode,
+    KeyEventKind,
+    KeyboardEnhancementFlags,
+    MouseButton,
+    MouseEventKind,
+    PopKeyboardEnhancementFlags,
+    PushKeyboardEnhancementFlags
+};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::supports_keyboard_enhancement;
+use ratatui::layout::Rect;
+use ratatui::style::Color as TermColor;
+
+use super::render::{
+    Color,
+    Renderer,
+    SpriteId
+};
+use super::territory;
+use super::{
+    set_debug,
+    Boxes,
+    BoxesError
+};
+
+const TICK: Duration = Duration::from_micros(8333);
+const FRAME: Duration = Duration::from_millis(33);
+
+const UP: u8 = 1;
+const DOWN: u8 = 2;
+const LEFT: u8 = 4;
+const RIGHT: u8 = 8;
+
+#[derive(Clone, Copy)]
+struct TuiCell {
+    glyph: Option<char>,
+    lines: u8,
+    fg: [f32; 3],
+    bg: [f32; 3],
+}
+
+// Draws the arena into a grid of terminal cells. Trails become box drawing characters,
+// the player an arrow pointing the way it faces and enemies letters.
+pub struct TuiRenderer {
+    arena: [f64; 2],
+    columns: u16,
+    rows: u16,
+    cells: Vec<TuiCell>,
+}
+
+impl TuiRenderer {
+    pub fn new(arena: [f64; 2], columns: u16, rows: u16) -> Self {
+        Self {
+            arena,
+            columns,
+            rows,
+            cells: vec![TuiCell { glyph: None, lines: 0, fg: [1.0; 3], bg: [0.0; 3] }; columns as usize * rows as usize],
+        }
+    }
+
+    fn cell_size(&self) -> [f64; 2] {
+        [self.arena[0] / self.columns as f64, self.arena[1] / self.rows as f64]
+    }
+
+    fn cell_at(&self, p: [f64; 2]) -> (i32, i32) {
+        let size = self.cell_size();
+        ((p[0] / size[0]).floor() as i32, (p[1] / size[1]).floor() as i32)
+    }
+
+    fn cell_mut(&mut self, col: i32, row: i32) -> Option<&mut TuiCell> {
+        if col < 0 || row < 0 || col >= self.columns as i32 || row >= self.rows as i32 {
+            return None;
+        }
+
+        self.cells.get_mut(row as usize * self.columns as usize + col as usize)
+    }
+
+    fn fill(&mut self, color: Color, inside: impl Fn([f64; 2]) -> bool) {
+        let size = self.cell_size();
+        for row in 0..self.rows as i32 {
+            for col in 0..self.columns as i32 {
+                let centre = [(col as f64 + 0.5) * size[0], (row as f64 + 0.5) * size[1]];
+                if inside(centre) {
+                    if let Some(cell) = self.cell_mut(col, row) {
+                        cell.bg = blend(cell.bg, color);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn render(&self, area: Rect, buf: &mut Buffer) {
+        for row in 0..self.rows.min(area.height) {
+            for col in 0..self.columns.min(area.width) {
+                let cell = self.cells[row as usize * self.columns as usize + col as usize];
+                let glyph = cell.glyph.unwrap_or_else(|| box_glyph(cell.lines));
+                if let Some(target) = buf.cell_mut((area.x + col, area.y + row)) {
+                    target.set_char(glyph).set_fg(term_color(cell.fg)).set_bg(term_color(cell.bg));
+                }
+            }
+        }
+    }
+}
+
+impl Renderer for TuiRenderer {
+    fn clear(&mut self, color: Color) {
+        for cell in self.cells.iter_mut() {
+            *cell = TuiCell { glyph: None, lines: 0, fg: [1.0; 3], bg: [color[0], color[1], color[2]] };
+        }
+    }
+
+    // Thin walls can fall between cell centres, so rectangles fill every cell they touch.
+    fn draw_rect(&mut self, color: Color, rect: [f64; 4]) {
+        let (c0, r0) = self.cell_at([rect[0], rect[1]]);
+        let (c1, r1) = self.cell_at([rect[0] + rect[2] - 0.001, rect[1] + rect[3] - 0.001]);
+        for row in r0..=r1 {
+            for col in c0..=c1 {
+                if let Some(cell) = self.cell_mut(col, row) {
+                    cell.bg = blend(cell.bg, color);
+                }
+            }
+        }
+    }
+
+    fn draw_line(&mut self, color: Color, _radius: f64, from: [f64; 2], to: [f64; 2]) {
+        let (c0, r0) = self.cell_at(from);
+        let (c1, r1) = self.cell_at(to);
+        let steps = (c1 - c0).abs().max((r1 - r0).abs());
+        let mut prev = (c0, r0);
+        for i in 0..=steps {
+            let t = if steps == 0 { 0.0 } else { i as f64 / steps as f64 };
+            let cur = (
+                (c0 as f64 + (c1 - c0) as f64 * t).round() as i32,
+                (r0 as f64 + (r1 - r0) as f64 * t).round() as i32
+            );
+            let (mut out, mut into) = (0, 0);
+            if cur.0 > prev.0 { out |= RIGHT; into |= LEFT; }
+            if cur.0 < prev.0 { out |= LEFT; into |= RIGHT; }
+            if cur.1 > prev.1 { out |= DOWN; into |= UP; }
+            if cur.1 < prev.1 { out |= UP; into |= DOWN; }
+            if let Some(cell) = self.cell_mut(prev.0, prev.1) {
+                cell.lines |= out;
+                cell.fg = [color[0], color[1], color[2]];
+            }
+            if let Some(cell) = self.cell_mut(cur.0, cur.1) {
+                cell.lines |= into;
+                cell.fg = [color[0], color[1], color[2]];
+            }
+            prev = cur;
+        }
+    }
+
+    fn draw_polygon(&mut self, color: Color, polygon: &[[f64; 2]]) {
+        let polygon = polygon.iter()
+            .map(|p| Position { x: p[0].round() as i32, y: p[1].round() as i32 })
+            .collect::<Vec<_>>();
+        self.fill(color, |p| territory::contains(&polygon, (p[0], p[1])));
+    }
+
+    fn draw_sprite(&mut self, sprite: SpriteId, src_rect: [f64; 4], position: [f64; 2], rotation: f64, tint: Color) {
+        let glyph = match sprite {
+            // The nearest of eight arrows, clockwise from facing right.
+            SpriteId::Hero => ['►', '↘', '▼', '↙', '◄', '↖', '▲', '↗'][(rotation.rem_euclid(360.0) / 45.0).round() as usize % 8],
+            SpriteId::Enemy if src_rect[0] > 0.0 => 'x',
+            SpriteId::Enemy => 'E',
+        };
+        let fg = match sprite {
+            SpriteId::Hero => [tint[0], tint[1], tint[2]],
+            SpriteId::Enemy => [0.1 * tint[0], 0.8 * tint[1], 0.1 * tint[2]],
+        };
+        let (col, row) = self.cell_at(position);
+        if let Some(cell) = self.cell_mut(col, row) {
+            cell.glyph = Some(glyph);
+            cell.fg = fg;
+        }
+    }
+
+    fn draw_text(&mut self, color: Color, text: &str, position: [f64; 2], _scale: f64) {
+        let (col, row) = self.cell_at(position);
+        let col = col.min(self.columns as i32 - text.chars().count() as i32).max(0);
+        for (i, c) in text.chars().enumerate() {
+            if let Some(cell) = self.cell_mut(col + i as i32, row) {
+                cell.glyph = Some(c);
+                cell.fg = [color[0], color[1], color[2]];
+            }
+        }
+    }
+}
+
+fn box_glyph(lines: u8) -> char {
+    match lines {
+        0 => ' ',
+        l if l == LEFT | RIGHT || l == LEFT || l == RIGHT => '─',
+        l if l == UP | DOWN || l == UP || l == DOWN => '│',
+        l if l == DOWN | RIGHT => '┌',
+        l if l == DOWN | LEFT => '┐',
+        l if l == UP | RIGHT => '└',
+        l if l == UP | LEFT => '┘',
+        l if l == UP | DOWN | RIGHT => '├',
+        l if l == UP | DOWN | LEFT => '┤',
+        l if l == DOWN | LEFT | RIGHT => '┬',
+        l if l == UP | LEFT | RIGHT => '┴',
+        _ => '┼',
+    }
+}
+
+fn blend(bg: [f32; 3], color: Color) -> [f32; 3] {
+    let a = color[3];
+    [
+        bg[0] * (1.0 - a) + color[0] * a,
+        bg[1] * (1.0 - a) + color[1] * a,
+        bg[2] * (1.0 - a) + color[2] * a
+    ]
+}
+
+fn term_color(c: [f32; 3]) -> TermColor {
+    TermColor::Rgb((c[0] * 255.0) as u8, (c[1] * 255.0) as u8, (c[2] * 255.0) as u8)
+}
+
+// Piston key codes follow SDL, where printable keys use their lower case ASCII value and
+// the function keys are numbered consecutively from F1.
+fn piston_key(code: KeyCode) -> Option<Key> {
+    match code {
+        KeyCode::Up => Some(Key::Up),
+        KeyCode::Down => Some(Key::Down),
+        KeyCode::Left => Some(Key::Left),
+        KeyCode::Right => Some(Key::Right),
+        KeyCode::Enter => Some(Key::Return),
+        KeyCode::Backspace => Some(Key::Backspace),
+        KeyCode::Tab => Some(Key::Tab),
+        KeyCode::F(n @ 1..=12) => Some(Key::from(Key::F1 as u32 + n as u32 - 1)),
+        KeyCode::Char(c) if c.is_ascii() => Some(Key::from(c.to_ascii_lowercase() as u32)),
+        _ => None,
+    }
+}
+
+impl Boxes {
+    // Runs the game in the terminal. Most terminals only report key presses, so unless the
+    // terminal supports release events a direction keeps going until another movement key
+    // or, when it isn't bound to anything, space is pressed. Other actions are released as
+    // soon as they are pressed.
+    pub fn run_tui(&mut self) -> Result<(), BoxesError> {
+        set_debug(false);
+        let mut terminal = ratatui::try_init().map_err(BoxesError::Terminal)?;
+        execute!(stdout(), EnableMouseCapture).map_err(BoxesError::Terminal)?;
+        let releases = supports_keyboard_enhancement().unwrap_or(false);
+        if releases {
+            execute!(stdout(), PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))
+                .map_err(BoxesError::Terminal)?;
+        }
+
+        let result = self.tui_loop(&mut terminal, releases);
+
+        if releases {
+            let _ = execute!(stdout(), PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(stdout(), DisableMouseCapture);
+        ratatui::restore();
+        result.map_err(BoxesError::Terminal)
+    }
+
+    fn tui_loop(&mut self, terminal: &mut DefaultTerminal, releases: bool) -> io::Result<()> {
+        // The movement key each player is holding.
+        let mut held: HashMap<usize, Key> = HashMap::new();
+        let mut last_frame = Instant::now() - FRAME;
+        let mut last_step = Instant::now();
+        #[cfg(feature = "gamepad")]
+        let mut gamepads = super::gilrs_input::GilrsInput::new();
+        self.show_title();
+        loop {
+            let tick_start = Instant::now();
+            #[cfg(feature = "gamepad")]
+            if let Some(gamepads) = gamepads.as_mut() {
+                for event in gamepads.poll() {
+                    self.gamepad_event(event);
+                }
+            }
+            while event::poll(Duration::ZERO)? {
+                let key = match event::read()? {
+                    Event::Key(key) => key,
+                    Event::Mouse(mouse) => {
+                        let size = terminal.size()?;
+                        let p = [
+                            (mouse.column as f64 + 0.5) * self.arena_size.width / size.width as f64,
+                            (mouse.row as f64 + 0.5) * self.arena_size.height / size.height as f64
+                        ];
+                        match mouse.kind {
+                            MouseEventKind::Down(MouseButton::Left) => self.pointer(Some(p), true, false),
+                            MouseEventKind::Drag(MouseButton::Left) => self.pointer(Some(p), false, true),
+                            MouseEventKind::Down(MouseButton::Right) => self.clear_waypoints(0),
+                            _ => {},
+                        }
+                        continue;
+                    },
+                    _ => continue,
+                };
+                // Letters typed into a name would otherwise move players or quit.
+                if let (true, KeyEventKind::Press, KeyCode::Char(c)) = (self.is_entering_name(), key.kind, key.code) {
+                    self.type_text(&c.to_string());
+                    continue;
+                }
+                let k = match piston_key(key.code) {
+                    Some(k) => k,
+                    None if key.code == KeyCode::Esc => return Ok(()),
+                    None => continue,
+                };
+                let bound = self.key_action(k);
+                match key.kind {
+                    KeyEventKind::Press if bound.is_none() && self.rebinding.is_none() && !self.is_entering_name() => match k {
+                        Key::Q => return Ok(()),
+                        Key::Space => {
+                            for (_, h) in held.drain() {
+                                self.release_key(h);
+                            }
+                        },
+                        _ => {},
+                    },
+                    KeyEventKind::Press if bound.is_some_and(|(_, a)| a.is_movement()) && self.rebinding.is_none() => {
+                        let player = bound.map(|(p, _)| p).unwrap_or_default();
+                        if let Some(h) = held.insert(player, k) {
+                            if !releases {
+                                self.release_key(h);
+                            }
+                        }
+                        self.press_key(k);
+                    },
+                    KeyEventKind::Press => {
+                        self.press_key(k);
+                        if !releases {
+                            self.release_key(k);
+                        }
+                    },
+                    KeyEventKind::Release => self.release_key(k),
+                    _ => {},
+                }
+            }
+
+            let now = Instant::now();
+            if self.screen.is_none() {
+                self.step(now - last_step);
+            }
+            self.update_spectators();
+            last_step = now;
+
+            if last_frame.elapsed() >= FRAME {
+                last_frame = Instant::now();
+                terminal.draw(|frame| {
+                    let area = frame.area();
+                    let mut renderer = TuiRenderer::new(
+                        [self.arena_size.width, self.arena_size.height],
+                        area.width,
+                        area.height
+                    );
+                    self.draw(&mut renderer);
+                    renderer.render(area, frame.buffer_mut());
+                })?;
+            }
+
+            if let Some(rest) = TICK.checked_sub(tick_start.elapsed()) {
+                thread::sleep(rest);
+            }
+        }
+    }
+}
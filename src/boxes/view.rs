@@ -0,0 +1,128 @@
+use piston_window::color;
+use serde::{
+    Deserialize,
+    Serialize
+};
+
+use super::font;
+use super::render::{
+    Color,
+    Renderer,
+    SpriteId
+};
+use super::{
+    blend,
+    lerp,
+    CLAIMED_COLOR,
+    DANGER_COLOR,
+    HUD_SCALE,
+    OBSTACLE_COLOR,
+    TRAIL_GRADIENT_STEPS,
+    TRAIL_RADIUS,
+    WAYPOINT_COLOR,
+    WARN_LINE_LEN
+};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SpriteView {
+    pub src_rect: [f64; 4],
+    pub position: [f64; 2],
+    pub rotation: f64,
+    pub tint: Color,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CaptureView {
+    pub color: Color,
+    pub polygon: Vec<[f64; 2]>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PlayerView {
+    pub trail: Color,
+    // Each segment as from x, from y, to x, to y.
+    pub lines: Vec<[f64; 4]>,
+    pub waypoints: Vec<[f64; 2]>,
+    pub sprite: Option<SpriteView>,
+    pub lives: u32,
+    pub caught: u32,
+}
+
+// Everything drawn for one frame, apart from the screens a front end shows over it. A
+// server sends these to its clients, so they can draw the game without simulating it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct View {
+    pub arena: [f64; 2],
+    pub claimed: Vec<[f64; 4]>,
+    pub obstacles: Vec<[f64; 4]>,
+    pub captures: Vec<CaptureView>,
+    pub players: Vec<PlayerView>,
+    pub enemies: Vec<SpriteView>,
+    // The claimed percentage and the target, in territory mode.
+    pub territory: Option<[f64; 2]>,
+    pub paused: bool,
+}
+
+impl View {
+    pub fn draw<R: Renderer>(&self, renderer: &mut R) {
+        renderer.clear(color::GRAY);
+        for r in self.claimed.iter() {
+            renderer.draw_rect(CLAIMED_COLOR, *r);
+        }
+        for o in self.obstacles.iter() {
+            renderer.draw_rect(OBSTACLE_COLOR, *o);
+        }
+        for capture in self.captures.iter() {
+            renderer.draw_polygon(capture.color, &capture.polygon);
+        }
+
+        for player in self.players.iter() {
+            draw_trail(renderer, player, self.territory.is_none());
+            for w in player.waypoints.iter() {
+                renderer.draw_rect(WAYPOINT_COLOR, [w[0] - 2.0, w[1] - 2.0, 4.0, 4.0]);
+            }
+        }
+
+        for enemy in self.enemies.iter() {
+            renderer.draw_sprite(SpriteId::Enemy, enemy.src_rect, enemy.position, enemy.rotation, enemy.tint);
+        }
+
+        for sprite in self.players.iter().filter_map(|p| p.sprite.as_ref()) {
+            renderer.draw_sprite(SpriteId::Hero, sprite.src_rect, sprite.position, sprite.rotation, sprite.tint);
+        }
+
+        // Bottom left, clear of where the players start.
+        let line_height = (font::GLYPH_HEIGHT + 2) as f64 * HUD_SCALE;
+        for (i, player) in self.players.iter().enumerate() {
+            let hud = format!("p{} lives {} caught {}", i + 1, player.lives, player.caught);
+            let y = self.arena[1] - (self.players.len() - i) as f64 * line_height;
+            renderer.draw_text(player.trail, &hud, [HUD_SCALE * 2.0, y], HUD_SCALE);
+        }
+
+        if let Some([claimed, target]) = self.territory {
+            let hud = format!("{:.0}% / {:.0}%", claimed, target);
+            let x = self.arena[0] - (font::text_width(&hud) + 2) as f64 * HUD_SCALE;
+            renderer.draw_text(color::BLACK, &hud, [x, HUD_SCALE * 2.0], HUD_SCALE);
+        }
+    }
+}
+
+fn draw_trail<R: Renderer>(renderer: &mut R, player: &PlayerView, resets: bool) {
+    for (i, l) in player.lines.iter().enumerate() {
+        let from = [l[0], l[1]];
+        let to = [l[2], l[3]];
+        if i != player.lines.len() - 1 {
+            renderer.draw_line(player.trail, TRAIL_RADIUS, from, to);
+            continue;
+        }
+
+        // The segment being drawn shades towards the danger colour as it
+        // approaches the length at which the trail is reset, in the modes that reset it.
+        let danger = if resets { ((to[0] - from[0]).hypot(to[1] - from[1]) / WARN_LINE_LEN).min(1.0) } else { 0.0 };
+        for step in 0..TRAIL_GRADIENT_STEPS {
+            let t0 = step as f64 / TRAIL_GRADIENT_STEPS as f64;
+            let t1 = (step + 1) as f64 / TRAIL_GRADIENT_STEPS as f64;
+            renderer.draw_line(blend(player.trail, DANGER_COLOR, t1 * danger), TRAIL_RADIUS, lerp(from, to, t0), lerp(from, to, t1));
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use piston_window::math::Matrix2d;
+use piston_window::{
+    Size,
+    Transformed
+};
+
+// Maps the fixed size arena the game is played in onto a window of any size. The arena
+// keeps its aspect ratio and is centred, leaving bars along the sides that don't fit.
+// Window sizes are in the logical units piston reports, so HiDPI displays get the same
+// layout at a higher pixel density.
+#[derive(Clone, Copy, Debug)]
+pub struct Viewport {
+    arena: Size,
+    window: Size,
+}
+
+impl Viewport {
+    pub fn new(arena: Size, window: Size) -> Self {
+        Self {
+            arena,
+            window,
+        }
+    }
+
+    pub fn get_arena(&self) -> Size {
+        self.arena
+    }
+
+    pub fn scale(&self) -> f64 {
+        let scale = (self.window.width / self.arena.width).min(self.window.height / self.arena.height);
+        if scale.is_finite() && scale > 0.0 {
+            scale
+        } else {
+            1.0
+        }
+    }
+
+    pub fn offset(&self) -> [f64; 2] {
+        let scale = self.scale();
+        [
+            (self.window.width - self.arena.width * scale) / 2.0,
+            (self.window.height - self.arena.height * scale) / 2.0
+        ]
+    }
+
+    // Converts a transform in window units into one in arena units.
+    pub fn transform(&self, transform: Matrix2d) -> Matrix2d {
+        let offset = self.offset();
+        let scale = self.scale();
+        transform.trans(offset[0], offset[1]).scale(scale, scale)
+    }
+
+    // Converts a point in window units, such as the mouse cursor, to arena units. Points
+    // in the bars outside the arena have no arena position.
+    pub fn arena_position(&self, p: [f64; 2]) -> Option<[f64; 2]> {
+        let offset = self.offset();
+        let scale = self.scale();
+        let x = (p[0] - offset[0]) / scale;
+        let y = (p[1] - offset[1]) / scale;
+        if x < 0.0 || y < 0.0 || x >= self.arena.width || y >= self.arena.height {
+            return None;
+        }
+
+        Some([x, y])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewport(width: f64, height: f64) -> Viewport {
+        Viewport::new(Size::from([800.0, 600.0]), Size::from([width, height]))
+    }
+
+    #[test]
+    fn same_aspect_ratio_fills_the_window() {
+        let viewport = viewport(1600.0, 1200.0);
+        assert_eq!(viewport.scale(), 2.0);
+        assert_eq!(viewport.offset(), [0.0, 0.0]);
+        assert_eq!(viewport.arena_position([0.0, 0.0]), Some([0.0, 0.0]));
+        assert_eq!(viewport.arena_position([1598.0, 1198.0]), Some([799.0, 599.0]));
+        assert_eq!(viewport.arena_position([1600.0, 600.0]), None);
+    }
+
+    #[test]
+    fn wider_window_has_bars_at_the_sides() {
+        let viewport = viewport(1000.0, 300.0);
+        assert_eq!(viewport.scale(), 0.5);
+        assert_eq!(viewport.offset(), [300.0, 0.0]);
+        assert_eq!(viewport.arena_position([500.0, 150.0]), Some([400.0, 300.0]));
+        assert_eq!(viewport.arena_position([300.0, 0.0]), Some([0.0, 0.0]));
+        assert_eq!(viewport.arena_position([299.0, 150.0]), None);
+        assert_eq!(viewport.arena_position([700.0, 150.0]), None);
+        assert_eq!(viewport.arena_position([950.0, 150.0]), None);
+    }
+
+    #[test]
+    fn taller_window_has_bars_above_and_below() {
+        let viewport = viewport(400.0, 900.0);
+        assert_eq!(viewport.scale(), 0.5);
+        assert_eq!(viewport.offset(), [0.0, 300.0]);
+        assert_eq!(viewport.arena_position([200.0, 450.0]), Some([400.0, 300.0]));
+        assert_eq!(viewport.arena_position([200.0, 299.0]), None);
+        assert_eq!(viewport.arena_position([200.0, 600.0]), None);
+        assert_eq!(viewport.arena_position([200.0, 10.0]), None);
+    }
+
+    #[test]
+    fn transform_maps_arena_corners_into_the_letterbox() {
+        let viewport = viewport(1000.0, 300.0);
+        let transform = viewport.transform([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]);
+        for (arena, window) in [([0.0, 0.0], [300.0, 0.0]), ([800.0, 600.0], [700.0, 300.0])] {
+            let x = transform[0][0] * arena[0] + transform[0][1] * arena[1] + transform[0][2];
+            let y = transform[1][0] * arena[0] + transform[1][1] * arena[1] + transform[1][2];
+            assert_eq!([x, y], window);
+        }
+    }
+
+    #[test]
+    fn empty_window_keeps_a_usable_scale() {
+        let viewport = viewport(0.0, 0.0);
+        assert_eq!(viewport.scale(), 1.0);
+        assert_eq!(viewport.arena_position([-400.0, -300.0]), Some([0.0, 0.0]));
+    }
+}
@@ -0,0 +1,22 @@
+pub mod boxes;
+
+extern crate glutin;
+extern crate image;
+extern crate opengl_graphics;
+extern crate piston;
+extern crate piston_window;
+extern crate rand;
+extern crate sprite;
+
+pub use boxes::{
+    set_debug,
+    Boxes,
+    BoxesError,
+    Conditions,
+    GameMode,
+    GamepadButton,
+    GamepadEvent,
+    Movement,
+    Preset,
+    Snapshot
+};
@@ -1,16 +1,153 @@
-pub mod boxes;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-extern crate image;
-extern crate opengl_graphics;
-extern crate piston;
-extern crate piston_window;
-extern crate rand;
-extern crate sprite;
-
-use boxes::Boxes;
+use boxes::{
+    Boxes,
+    Conditions,
+    GameMode,
+    Movement,
+    Preset
+};
 
+const SPECTATE_ADDR: &str = "127.0.0.1:7780";
 
 fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
     let mut boxes = Boxes::new(800, 800, 1, 10);
-    boxes.run();
+    if let Some(i) = args.iter().position(|a| a == "--assets") {
+        if let Some(root) = args.get(i + 1) {
+            boxes.set_asset_root(PathBuf::from(root));
+        }
+    }
+    for (i, _) in args.iter().enumerate().filter(|(_, a)| *a == "--pack") {
+        if let Some(path) = args.get(i + 1) {
+            match boxes.mount_pack(Path::new(path)) {
+                Ok(manifest) => println!("mounted pack {} {}", manifest.name, manifest.version),
+                Err(e) => println!("failed to mount pack {}: {}", path, e),
+            }
+        }
+    }
+    if let Some(i) = args.iter().position(|a| a == "--seed") {
+        if let Some(seed) = args.get(i + 1).and_then(|t| t.parse::<u64>().ok()) {
+            boxes.set_seed(seed);
+        }
+    }
+    if let Some(i) = args.iter().position(|a| a == "--territory") {
+        let target = args.get(i + 1).and_then(|t| t.parse::<f64>().ok()).unwrap_or(75.0);
+        boxes.set_mode(GameMode::Territory(target));
+    }
+    if args.iter().any(|a| a == "--versus") {
+        boxes.set_mode(GameMode::Versus);
+    }
+    if let Some(i) = args.iter().position(|a| a == "--bindings") {
+        if let Some(path) = args.get(i + 1) {
+            boxes.set_bindings_path(PathBuf::from(path));
+        }
+    }
+    if let Some(i) = args.iter().position(|a| a == "--preset") {
+        match args.get(i + 1).map(|p| p.as_str()) {
+            Some("arrows") => boxes.set_preset(Preset::Arrows),
+            Some("wasd") => boxes.set_preset(Preset::Wasd),
+            Some("vim") => boxes.set_preset(Preset::Vim),
+            p => println!("unknown key preset {}, expected arrows, wasd or vim", p.unwrap_or_default()),
+        }
+    }
+    if let Some(i) = args.iter().position(|a| a == "--movement") {
+        match args.get(i + 1).map(|m| m.as_str()) {
+            Some("4") => boxes.set_movement(Movement::Cardinal),
+            Some("8") => boxes.set_movement(Movement::EightWay),
+            Some("analog") => boxes.set_movement(Movement::Analog),
+            m => println!("unknown movement {}, expected 4, 8 or analog", m.unwrap_or_default()),
+        }
+    }
+    if let Some(i) = args.iter().position(|a| a == "--players") {
+        if let Some(count) = args.get(i + 1).and_then(|t| t.parse::<usize>().ok()) {
+            boxes.set_players(count);
+        }
+    }
+    if args.iter().any(|a| a == "--shared-loops") {
+        boxes.set_shared_loops(true);
+    }
+    if args.iter().any(|a| a == "--fullscreen") {
+        boxes.set_fullscreen(true);
+    }
+    if let Some(i) = args.iter().position(|a| a == "--capture-fade") {
+        if let Some(ms) = args.get(i + 1).and_then(|t| t.parse::<u64>().ok()) {
+            boxes.set_capture_fade(Duration::from_millis(ms));
+        }
+    }
+    if let Some(i) = args.iter().position(|a| a == "--save") {
+        if let Some(path) = args.get(i + 1) {
+            boxes.set_save_path(PathBuf::from(path));
+        }
+    }
+    if let Some(i) = args.iter().position(|a| a == "--highscores") {
+        if let Some(path) = args.get(i + 1) {
+            boxes.set_high_scores_path(PathBuf::from(path));
+        }
+    }
+    if let Some(i) = args.iter().position(|a| a == "--record") {
+        if let Some(path) = args.get(i + 1) {
+            if let Err(e) = boxes.record(Path::new(path)) {
+                println!("failed to record to {}: {}", path, e);
+            }
+        }
+    }
+    if let Some(i) = args.iter().position(|a| a == "--load") {
+        if let Some(path) = args.get(i + 1) {
+            if let Err(e) = boxes.load_game(Path::new(path)) {
+                eprintln!("boxes: {}", e);
+                eprintln!("{}", e.hint());
+                std::process::exit(1);
+            }
+        }
+    }
+    let arg = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1));
+    let conditions = Conditions {
+        latency: Duration::from_millis(arg("--latency").and_then(|t| t.parse::<u64>().ok()).unwrap_or(0)),
+        loss: arg("--loss").and_then(|t| t.parse::<f64>().ok()).unwrap_or(0.0) / 100.0,
+    };
+    let connected = match (arg("--connect"), arg("--peer")) {
+        (Some(addr), _) => boxes.connect(addr, conditions),
+        (None, Some(peer)) => {
+            let listen = arg("--listen").map(|a| a.as_str()).unwrap_or("127.0.0.1:7778");
+            boxes.connect_peer(listen, peer, conditions)
+        },
+        (None, None) => Ok(()),
+    };
+    // The address is optional, so a following flag isn't taken for it.
+    let spectated = match args.iter().position(|a| a == "--spectate") {
+        Some(i) => {
+            let addr = args.get(i + 1).filter(|a| !a.starts_with("--")).map(|a| a.as_str());
+            boxes.spectate(addr.unwrap_or(SPECTATE_ADDR))
+        },
+        None => Ok(()),
+    };
+    if let Err(e) = connected.and(spectated) {
+        eprintln!("boxes: {}", e);
+        eprintln!("{}", e.hint());
+        std::process::exit(1);
+    }
+    let result = match args.get(1).map(|a| a.as_str()) {
+        Some("tui") => boxes.run_tui(),
+        Some("screenshot") => {
+            boxes::set_debug(false);
+            let path = args.get(2).map(|p| p.as_str()).unwrap_or("boxes.png");
+            let ticks = args.get(3).and_then(|t| t.parse::<u32>().ok()).unwrap_or(0);
+            boxes.screenshot(Path::new(path), ticks)
+        },
+        Some("export") => {
+            boxes::set_debug(false);
+            let inputs = arg("--inputs").map(|p| p.as_str()).unwrap_or("boxes.rec");
+            let out = arg("--out").map(|p| p.as_str()).unwrap_or("boxes.gif");
+            let every = arg("--every").and_then(|n| n.parse::<usize>().ok()).unwrap_or(1);
+            boxes.export(Path::new(inputs), Path::new(out), every)
+        },
+        _ => boxes.run(),
+    };
+    if let Err(e) = result {
+        eprintln!("boxes: {}", e);
+        eprintln!("{}", e.hint());
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,104 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use boxes::{
+    Boxes,
+    Preset
+};
+use piston_window::Key;
+
+const GOLDEN: &str = "tests/fixtures/seeded_scene.png";
+const TICK: Duration = Duration::from_micros(4167);
+// How far a channel may drift, and how many pixels may drift further, before a frame no
+// longer counts as matching. Enough for a change of encoder or filtering, not of content.
+const CHANNEL_TOLERANCE: u8 = 2;
+const MAX_DIFFERENT_PIXELS: usize = 16;
+
+// A scratch directory per test, so tests running in parallel don't share files.
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("boxes-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Uses the built in assets, so a local assets directory can't change the output.
+fn seeded_game(seed: u64) -> Boxes {
+    boxes::set_debug(false);
+    let mut boxes = Boxes::new(800, 800, 1, 10);
+    boxes.set_asset_root(PathBuf::from("tests/fixtures/no-assets"));
+    boxes.set_preset(Preset::Arrows);
+    boxes.set_players(2);
+    boxes.set_seed(seed);
+    boxes
+}
+
+// Player one heads right then down and player two, on WASD, down then right, so the
+// frame has trails, turns and rotated heroes in it.
+fn play(boxes: &mut Boxes) {
+    for (keys, ticks) in [([Key::Right, Key::S], 60), ([Key::Down, Key::D], 60)] {
+        for key in keys {
+            boxes.press_key(key);
+        }
+        for _ in 0..ticks {
+            boxes.step(TICK);
+        }
+        for key in keys {
+            boxes.release_key(key);
+        }
+    }
+}
+
+fn read_dir(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+    let mut files = fs::read_dir(dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .map(|path| (path.file_name().unwrap().into(), fs::read(&path).unwrap()))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+// Pixels rather than file bytes are compared, so a new PNG encoder doesn't break the test.
+// Set BOXES_BLESS=1 to rewrite the golden image after an intended change to rendering.
+#[test]
+fn seeded_scene_matches_golden_image() {
+    let dir = scratch("golden");
+    let out = dir.join("scene.png");
+    let mut boxes = seeded_game(1234);
+    play(&mut boxes);
+    boxes.screenshot(&out, 60).unwrap();
+    if std::env::var_os("BOXES_BLESS").is_some() {
+        fs::copy(&out, GOLDEN).unwrap();
+    }
+
+    let frame = image::open(&out).unwrap().to_rgba8();
+    let golden = image::open(GOLDEN).unwrap().to_rgba8();
+    assert_eq!(frame.dimensions(), golden.dimensions());
+    let different = frame.pixels().zip(golden.pixels())
+        .filter(|(a, b)| a.0.iter().zip(b.0).any(|(a, b)| a.abs_diff(b) > CHANNEL_TOLERANCE))
+        .count();
+    assert!(different <= MAX_DIFFERENT_PIXELS, "{} pixels of {} differ from {}", different, out.display(), GOLDEN);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn export_is_deterministic() {
+    let dir = scratch("export");
+    let recording = dir.join("run.rec");
+    {
+        let mut boxes = seeded_game(99);
+        boxes.record(&recording).unwrap();
+        play(&mut boxes);
+    }
+
+    for run in ["a", "b"] {
+        seeded_game(0).export(&recording, &dir.join(format!("{}.gif", run)), 40).unwrap();
+        seeded_game(0).export(&recording, &dir.join(run), 30).unwrap();
+    }
+    assert!(fs::read(dir.join("a.gif")).unwrap() == fs::read(dir.join("b.gif")).unwrap(), "GIF exports differ");
+    let (a, b) = (read_dir(&dir.join("a")), read_dir(&dir.join("b")));
+    assert_eq!(a.len(), 4);
+    assert!(a == b, "PNG exports differ");
+    fs::remove_dir_all(dir).unwrap();
+}
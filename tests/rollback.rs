@@ -0,0 +1,48 @@
+use std::net::UdpSocket;
+use std::thread;
+use std::time::Duration;
+
+use boxes::{
+    Boxes,
+    Conditions,
+    GameMode
+};
+
+const FRAMES: u64 = 600;
+
+// Plays two rollback peers against each other in this process over loopback UDP, each on
+// its own thread, and returns each side's checksum and rollback count. Both sockets are
+// bound on ports the system picks before either peer starts, so each knows the other's.
+fn play(mode: GameMode, conditions: Conditions) -> Vec<(u64, u64)> {
+    boxes::set_debug(false);
+    let sockets = [0, 1].map(|_| UdpSocket::bind("127.0.0.1:0").unwrap());
+    let addrs = sockets.each_ref().map(|s| s.local_addr().unwrap().to_string());
+    let peers = sockets.into_iter().enumerate().map(|(i, socket)| {
+        let peer = addrs[1 - i].clone();
+        thread::spawn(move || {
+            let mut boxes = Boxes::new(800, 800, 1, 10);
+            boxes.set_seed(3);
+            boxes.set_mode(mode);
+            boxes.connect_peer_on(socket, &peer, conditions).unwrap();
+            boxes.play_loopback(FRAMES).expect("peer stopped playing")
+        })
+    }).collect::<Vec<_>>();
+    peers.into_iter().map(|p| p.join().unwrap()).collect()
+}
+
+fn assert_in_sync(results: &[(u64, u64)]) {
+    assert_eq!(results[0].0, results[1].0, "peers desynced: {:?}", results);
+    assert!(results.iter().any(|(_, rollbacks)| *rollbacks > 0), "no rollbacks happened: {:?}", results);
+}
+
+#[test]
+fn peers_stay_in_sync_through_rollbacks() {
+    let conditions = Conditions { latency: Duration::from_millis(60), loss: 0.05 };
+    assert_in_sync(&play(GameMode::Capture, conditions));
+}
+
+#[test]
+fn versus_peers_stay_in_sync_through_rollbacks() {
+    let conditions = Conditions { latency: Duration::from_millis(120), loss: 0.15 };
+    assert_in_sync(&play(GameMode::Versus, conditions));
+}
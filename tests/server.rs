@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::net::{SocketAddr, UdpSocket};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// The server binary, killed when the test is done with it however the test ends.
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// Starts a server on a port the system picks and returns the address it says it bound.
+fn serve(players: usize) -> (Server, SocketAddr) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_boxes-server"))
+        .args(["--bind", "127.0.0.1:0", "--seed", "5", "--players", &players.to_string()])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let server = Server(child);
+    while let Some(line) = lines.next() {
+        if let Some((_, addr)) = line.unwrap().split_once(" players on ") {
+            // The server logs as it goes, and would fail to if nothing read its output.
+            thread::spawn(move || lines.for_each(drop));
+            return (server, addr.parse().unwrap());
+        }
+    }
+    panic!("server exited without saying where it was serving");
+}
+
+// A client speaking the wire protocol directly, so the test sees exactly what is sent.
+struct Client {
+    socket: UdpSocket,
+    server: SocketAddr,
+    parts: HashMap<u64, Vec<Option<String>>>,
+}
+
+impl Client {
+    fn join(server: SocketAddr) -> (Self, u64) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        let mut client = Self { socket, server, parts: HashMap::new() };
+        let start = Instant::now();
+        while start.elapsed() < TIMEOUT {
+            client.send(&json!("Join"));
+            if let Some(welcome) = client.recv().and_then(|m| m.get("Welcome").cloned()) {
+                return (client, welcome["player"].as_u64().unwrap());
+            }
+        }
+        panic!("server never welcomed the client");
+    }
+
+    fn send(&self, message: &Value) {
+        self.socket.send_to(&serde_json::to_vec(message).unwrap(), self.server).unwrap();
+    }
+
+    fn press(&self, sequence: u64, action: &str) {
+        self.send(&json!({ "Input": [[sequence, { "Pressed": action }]] }));
+    }
+
+    fn recv(&mut self) -> Option<Value> {
+        let mut buf = vec![0; 65507];
+        let (len, _) = self.socket.recv_from(&mut buf).ok()?;
+        serde_json::from_slice(&buf[..len]).ok()
+    }
+
+    // The next whole snapshot, with the last input of ours it says the server applied.
+    fn snapshot(&mut self) -> (u64, Value) {
+        let start = Instant::now();
+        while start.elapsed() < TIMEOUT {
+            let snapshot = match self.recv().and_then(|m| m.get("Snapshot").cloned()) {
+                Some(snapshot) => snapshot,
+                None => continue,
+            };
+            let tick = snapshot["tick"].as_u64().unwrap();
+            let parts = snapshot["parts"].as_u64().unwrap() as usize;
+            let buffer = self.parts.entry(tick).or_insert_with(|| vec![None; parts]);
+            buffer[snapshot["part"].as_u64().unwrap() as usize] = snapshot["view"].as_str().map(String::from);
+            if buffer.iter().all(|p| p.is_some()) {
+                let json = self.parts.remove(&tick).unwrap().into_iter().flatten().collect::<String>();
+                return (snapshot["ack"].as_u64().unwrap(), serde_json::from_str(&json).unwrap());
+            }
+        }
+        panic!("no snapshot arrived");
+    }
+}
+
+fn position(view: &Value, player: u64) -> (f64, f64) {
+    let p = &view["players"][player as usize]["sprite"]["position"];
+    (p[0].as_f64().unwrap(), p[1].as_f64().unwrap())
+}
+
+#[test]
+fn clients_input_moves_them_in_snapshots() {
+    let (_server, addr) = serve(2);
+    let (mut host, host_player) = Client::join(addr);
+    let (mut guest, guest_player) = Client::join(addr);
+    assert_eq!((host_player, guest_player), (0, 1));
+
+    let (_, view) = host.snapshot();
+    let start = position(&view, host_player);
+    host.press(1, "MoveRight");
+    // A guest's pause would stop the host too, so only the host may pause.
+    guest.press(1, "Pause");
+
+    let started = Instant::now();
+    loop {
+        let (ack, view) = host.snapshot();
+        let (x, y) = position(&view, host_player);
+        if ack >= 1 && x > start.0 + 10.0 {
+            assert_eq!(y, start.1);
+            break;
+        }
+        assert!(started.elapsed() < TIMEOUT, "host never moved from {:?}", start);
+    }
+
+    loop {
+        let (ack, view) = guest.snapshot();
+        if ack >= 1 {
+            assert_eq!(view["paused"], json!(false));
+            break;
+        }
+        assert!(started.elapsed() < TIMEOUT, "guest's pause was never acknowledged");
+    }
+}